@@ -0,0 +1,6 @@
+#![allow(clippy::needless_return, clippy::unused_unit)]
+
+pub mod scanning;
+pub mod parsing;
+pub mod preprocessing;
+pub mod logging;
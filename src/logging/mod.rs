@@ -0,0 +1,26 @@
+use std::env;
+use std::sync::OnceLock;
+
+const LOG_VARIABLE: &str = "MINI_EXCEL_LOG";
+
+// Tracing is enabled with `MINI_EXCEL_LOG=debug`, the variable is only read once
+pub fn enabled() -> bool
+{
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+
+    *ENABLED.get_or_init(|| env::var(LOG_VARIABLE).is_ok_and(|level| level.eq_ignore_ascii_case("debug")))
+}
+
+// Writes to stderr when tracing is enabled, the arguments aren't formatted otherwise
+macro_rules! debug
+{
+    ($($arg:tt)*) =>
+    {
+        if $crate::logging::enabled()
+        {
+            eprintln!("[debug] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use debug;
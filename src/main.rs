@@ -1,17 +1,233 @@
 use std::env;
 use std::fs;
 use std::io;
-use std::io::Read;
-use std::io::Write;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::process;
+use std::time::Instant;
 
-use parsing::Parser;
+use mini_excel::parsing::{self, json_string, Alignment, Cell, CellRef, EvalError, Evaluator, FormatOptions, LoadOptions, NumberFormat, Sheet, Table, Width, DEFAULT_EPSILON};
+use mini_excel::preprocessing;
+use mini_excel::scanning::{CellIndex, LiteralValue};
 
-mod scanning;
-mod parsing;
+const CONFIG_FILE: &str = ".mini-excel.toml";
 
 fn usage(program_name: String) -> String
 {
-    format!("{} <input>", program_name)
+    format!("{} [options] <input>...\n       {} [options] --diff <old> <new>\n       {} [options] --explain-deps <cell> <input>\n       {} --repl",
+        program_name, program_name, program_name, program_name)
+}
+
+fn flag_value(args: &mut Vec<String>, flag: &str) -> io::Result<String>
+{
+    if args.is_empty()
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Missing value for `{}`", flag)));
+    }
+
+    Ok(args.remove(0))
+}
+
+// A column of `--columns` or `--col-format`, letters counted like in `CellRef::text_to_number` (`A` is 0,
+// `C` is 2) or the number of the column like in the labels (letters name the rows there, so `A2` is in column 2)
+fn parse_column(column: &str) -> Option<usize>
+{
+    let column = column.trim();
+
+    if !column.is_empty() && column.chars().all(|c| c.is_ascii_alphabetic())
+    {
+        // Too many letters overflow
+        return parsing::catch_panic(|| CellRef::text_to_number(column.to_string())).ok();
+    }
+
+    column.parse::<usize>().ok()
+}
+
+// Turns the `key = value` lines of the config file into the matching flags, so that the
+// flags given on the command line come after them and take precedence
+fn config_args(path: &Path) -> io::Result<Vec<String>>
+{
+    let content = match fs::read_to_string(path)
+    {
+        Ok(content) => content,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+
+    let mut args = Vec::<String>::new();
+
+    for line in content.lines().map(str::trim)
+    {
+        if line.is_empty() || line.starts_with('#')
+        {
+            continue;
+        }
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid option `{}` in `{}`", line, path.display()));
+
+        let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+        let key = key.trim().replace('_', "-");
+        let value = value.trim();
+        let value = match value.strip_prefix('"').and_then(|value| value.split_once('"'))
+        {
+            Some((quoted, _)) => quoted,
+            None => value.split('#').next().unwrap().trim(),
+        };
+
+        match (key.as_str(), value)
+        {
+            // The name other tools give to `round-output`
+            ("precision", _) => args.extend([String::from("--round-output"), value.to_string()]),
+            ("width" | "align" | "null-value" | "thousands-separator" | "round-output" | "sheet-name" | "columns" | "col-format" | "output" | "output-dir" | "output-name" | "header-rows" | "epsilon" | "seed", _) => args.extend([format!("--{}", key), value.to_string()]),
+            ("emit-formulas" | "with-formulas" | "trim-blank-rows" | "color" | "quiet" | "lint" | "profile" | "force-recalc" | "seed-per-cell" | "fail-fast" | "no-eval" | "validate-only" | "tab-width", "true") => args.push(format!("--{}", key)),
+            ("color", "false") => args.push(String::from("--no-color")),
+            ("emit-formulas" | "with-formulas" | "trim-blank-rows" | "quiet" | "lint" | "profile" | "force-recalc" | "seed-per-cell" | "fail-fast" | "no-eval" | "validate-only" | "tab-width", "false") => (),
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(args)
+}
+
+// Evaluates each line of stdin and prints its value, `set A1 = <expression>` stores the value of the
+// expression in `A1` so the next lines can refer to it. Stops at the end of the input
+fn run_repl() -> io::Result<()>
+{
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+
+    let mut cells = Table::new();
+
+    let mut lines = stdin.lock().lines();
+
+    loop
+    {
+        // A prompt would only clutter the output of a piped script
+        if interactive
+        {
+            print!("> ");
+            io::stdout().flush()?;
+        }
+
+        let line = match lines.next()
+        {
+            Some(line) => line?,
+            None => break,
+        };
+
+        let line = line.trim();
+
+        if line.is_empty()
+        {
+            continue;
+        }
+
+        let result = parsing::catch_panic(|| match line.strip_prefix("set ").map(|assignment| assignment.split_once('='))
+        {
+            Some(Some((label, expression))) =>
+            {
+                let index = CellIndex::from_label(label.trim())
+                    .unwrap_or_else(|| panic!("Invalid cell `{}`, expected a cell like `A1`", label.trim()));
+
+                let value = parsing::eval_expression_in(expression, &cells)?;
+                cells.insert(index, Cell::from_literal(&value));
+
+                Ok(value)
+            },
+            Some(None) => panic!("Expected `set <cell> = <expression>` (e.g. `set A1 = 5`)"),
+            None => parsing::eval_expression_in(line, &cells),
+        });
+
+        match result
+        {
+            Ok(Ok(value)) => println!("{}", Cell::from_literal(&value).as_value().unwrap()),
+            Ok(Err(error)) => eprintln!("error: {}", error),
+            Err(message) => eprintln!("error: {}", message),
+        }
+    }
+
+    // Ends the prompt's line
+    if interactive
+    {
+        println!();
+    }
+
+    Ok(())
+}
+
+// Prints the cells `label` depends on as a tree, without evaluating anything
+fn run_explain_deps(filename: &str, label: &str, load_options: &LoadOptions) -> io::Result<()>
+{
+    let input = preprocessing::expand_includes(Path::new(filename))?;
+    let sheet = Sheet::with_options(&input, load_options);
+
+    let index = sheet.find_cell(label).ok_or_else(||
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid cell `{}`", label)))?;
+
+    println!("{}", sheet.explain_dependencies(&index));
+
+    Ok(())
+}
+
+// Prints the cells whose values differ between the evaluated sheets, `+` for a cell only `new` has, `-` for
+// one only `old` has and `~` for a changed value, with the difference of numbers. Exits with `1` if there's any
+fn run_diff(evaluator: &mut Evaluator, old: &str, new: &str, epsilon: f64) -> io::Result<()>
+{
+    let mut evaluate = |filename: &str| -> io::Result<Sheet>
+    {
+        let input = preprocessing::expand_includes(Path::new(filename))?;
+
+        evaluator.evaluate(&input)
+            .cloned()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", filename, error)))
+    };
+
+    let old_sheet = evaluate(old)?;
+    let new_sheet = evaluate(new)?;
+
+    let diffs = old_sheet.diff(&new_sheet, epsilon);
+    let format = |value: &LiteralValue| Cell::from_literal(value).as_value().unwrap().to_string();
+    let number = |value: &LiteralValue| match value
+    {
+        LiteralValue::Float(f) => Some(*f),
+        LiteralValue::Int(i) => Some(*i as f64),
+        _ => None,
+    };
+
+    for diff in &diffs
+    {
+        let cell = match diff.sheet.as_str()
+        {
+            "" => diff.cell.to_string(),
+            sheet => format!("{}!{}", sheet, diff.cell),
+        };
+
+        match (&diff.old, &diff.new)
+        {
+            (None, Some(new)) => println!("+ {}: {}", cell, format(new)),
+            (Some(old), None) => println!("- {}: {}", cell, format(old)),
+            // Integers keep an exact difference, even one that doesn't fit in an integer
+            (Some(LiteralValue::Int(a)), Some(LiteralValue::Int(b))) =>
+            {
+                println!("~ {}: {} -> {} ({:+})", cell, a, b, *b as i128 - *a as i128);
+            },
+            (Some(old), Some(new)) if number(old).is_some() && number(new).is_some() =>
+            {
+                let (a, b) = (number(old).unwrap(), number(new).unwrap());
+                let change = format(&LiteralValue::Float(b - a));
+                println!("~ {}: {} -> {} ({}{})", cell, format(old), format(new), if b >= a { "+" } else { "" }, change);
+            },
+            (Some(old), Some(new)) => println!("~ {}: {} -> {}", cell, format(old), format(new)),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    if !diffs.is_empty()
+    {
+        process::exit(1);
+    }
+
+    Ok(())
 }
 
 fn main() -> io::Result<()>
@@ -20,33 +236,452 @@ fn main() -> io::Result<()>
 
     let program = args.remove(0);
 
-    if args.len() != 1
+    let mut args = [config_args(Path::new(CONFIG_FILE))?, args].concat();
+
+    let mut options = FormatOptions::default();
+    let mut inputs = Vec::<String>::new();
+    let mut output_filename = String::from("output");
+    let mut output_dir = Option::<String>::None;
+    let mut output_name = String::from("{name}.out");
+    let mut color = false;
+    let mut lint = false;
+    let mut profile = false;
+    let mut quiet = false;
+    let mut force_recalc = false;
+    let mut no_eval = false;
+    let mut validate_only = false;
+    let mut load_options = LoadOptions::default();
+    let mut epsilon = DEFAULT_EPSILON;
+    let mut seed = Option::<u64>::None;
+    let mut repl = false;
+    let mut diff = false;
+    let mut fail_fast = false;
+    let mut seed_per_cell = false;
+    let mut ast_json = false;
+    let mut explain_deps = Option::<String>::None;
+
+    while !args.is_empty()
+    {
+        let arg = args.remove(0);
+
+        match arg.as_str()
+        {
+            "--width" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+
+                options.width = match value.as_str()
+                {
+                    "auto" => Width::Auto,
+                    _ => Width::Fixed(value.parse::<usize>().map_err(|_|
+                        io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid width `{}`", value)))?),
+                };
+            },
+            "--align" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+
+                options.alignment = match value.as_str()
+                {
+                    "left"   => Alignment::Left,
+                    "right"  => Alignment::Right,
+                    "center" => Alignment::Center,
+                    "auto"   => Alignment::Auto,
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid alignment `{}`", value))),
+                };
+            },
+            "--emit-formulas" => options.emit_formulas = true,
+            "--with-formulas" => options.with_formulas = true,
+            "--trim-blank-rows" => options.trim_blank_rows = true,
+            "--round-output" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+
+                options.round_output = Some(value.parse::<usize>().map_err(|_|
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid number of decimals `{}`", value)))?);
+            },
+            "--null-value" => options.null_value = Some(flag_value(&mut args, &arg)?),
+            "--columns" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+                let mut columns = Vec::<usize>::new();
+
+                for column in value.split(',')
+                {
+                    match parse_column(column)
+                    {
+                        Some(column) if !columns.contains(&column) => columns.push(column),
+                        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid columns `{}`, expected distinct column letters or numbers (e.g. `A,C` or `0,2`)", value))),
+                    }
+                }
+
+                options.columns = Some(columns);
+            },
+            "--col-format" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+                let invalid = || io::Error::new(io::ErrorKind::InvalidInput,
+                    format!("Invalid column formats `{}`, expected `<column>:<format>` pairs (e.g. `B:currency,C:0.0%`)", value));
+
+                // Columns are like in `--columns`, a format is a name or a format of `text`
+                for pair in value.split(',')
+                {
+                    let (column, format) = pair.split_once(':').ok_or_else(invalid)?;
+                    let column = parse_column(column).ok_or_else(invalid)?;
+
+                    let format = match format.trim()
+                    {
+                        "currency" => "$0.00",
+                        "percent"  => "0%",
+                        format     => format,
+                    };
+
+                    options.column_formats.insert(column, NumberFormat::parse(format).ok_or_else(invalid)?);
+                }
+            },
+            "--sheet-name" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+
+                // Written as a `=== Name ===` line, so it must read back as one
+                if !Sheet::is_sheet_name(&value)
+                {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid sheet name `{}`, expected a letter followed by letters, digits or `_`", value)));
+                }
+
+                options.sheet_name = Some(value);
+            },
+            "--thousands-separator" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+
+                let mut chars = value.chars();
+                options.thousands_separator = match (chars.next(), chars.next())
+                {
+                    (Some(separator), None) if !separator.is_ascii_digit() => Some(separator),
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid thousands separator `{}`, expected a single character", value))),
+                };
+            },
+            "-o" | "--output" => output_filename = flag_value(&mut args, &arg)?,
+            "--output-dir" => output_dir = Some(flag_value(&mut args, &arg)?),
+            "--output-name" => output_name = flag_value(&mut args, &arg)?,
+            "--color" => color = true,
+            "--no-color" => color = false,
+            "--lint" => lint = true,
+            "--profile" => profile = true,
+            "-q" | "--quiet" => quiet = true,
+            "--force-recalc" => force_recalc = true,
+            "--fail-fast" => fail_fast = true,
+            "--seed-per-cell" => seed_per_cell = true,
+            "--no-eval" => no_eval = true,
+            "--tab-width" => load_options.tab_width = true,
+            "--validate-only" => validate_only = true,
+            "--ast-json" => ast_json = true,
+            "--repl" => repl = true,
+            "--diff" => diff = true,
+            "--explain-deps" => explain_deps = Some(flag_value(&mut args, &arg)?),
+            "--header-rows" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+
+                load_options.header_rows = value.parse::<usize>().map_err(|_|
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid number of header rows `{}`", value)))?;
+            },
+            "--seed" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+
+                seed = Some(value.parse::<u64>().map_err(|_|
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid seed `{}`", value)))?);
+            },
+            "--epsilon" =>
+            {
+                let value = flag_value(&mut args, &arg)?;
+
+                epsilon = value.parse::<f64>().ok().filter(|epsilon| *epsilon >= 0.0).ok_or_else(||
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid epsilon `{}`", value)))?;
+            },
+            _ => inputs.push(arg),
+        }
+    }
+
+    if repl
+    {
+        return run_repl();
+    }
+
+    // Several inputs would overwrite each other's output without a directory to name them in
+    if inputs.is_empty() || (inputs.len() > 1 && output_dir.is_none() && !validate_only && !ast_json && !diff) || (diff && inputs.len() != 2) || (explain_deps.is_some() && inputs.len() != 1)
     {
         return Err(io::Error::new(io::ErrorKind::InvalidInput, usage(program)));
     }
 
-    // Reading file
-    let input_filename = args.remove(0);
+    // Without a seed the values would still change on every run
+    if seed_per_cell && seed.is_none()
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "`--seed-per-cell` needs a `--seed`"));
+    }
 
-    let input_file = fs::File::open(&input_filename);
+    if let Some(label) = &explain_deps
+    {
+        return run_explain_deps(&inputs[0], label, &load_options);
+    }
 
-    if let Err(_value) = input_file
+    if let Some(dir) = &output_dir
     {
-        return Err(io::Error::new(io::ErrorKind::NotFound, format!("File `{}` not found", &input_filename)));
+        fs::create_dir_all(dir)?;
     }
 
-    let mut input = String::new();
+    let mut diagnostics = Vec::<String>::new();
+    let mut failed = false;
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_force_recalc(force_recalc);
+    evaluator.set_header_rows(load_options.header_rows);
+    evaluator.set_tab_width(load_options.tab_width);
+    evaluator.set_epsilon(epsilon);
+    evaluator.set_seed(seed);
+    evaluator.set_seed_per_cell(seed_per_cell);
+    evaluator.set_fail_fast(fail_fast);
+
+    if diff
+    {
+        return run_diff(&mut evaluator, &inputs[0], &inputs[1], epsilon);
+    }
+
+    for input_filename in inputs
+    {
+        // Reading file
+        let start = Instant::now();
+        let input = preprocessing::expand_includes(Path::new(&input_filename))?;
+        let read = start.elapsed();
+
+        // Only parsed, the formulas aren't evaluated
+        if ast_json
+        {
+            for tree in Sheet::with_options(&input, &load_options).syntax_trees()
+            {
+                let cell = match tree.sheet.as_str()
+                {
+                    "" => tree.cell.to_string(),
+                    sheet => format!("{}!{}", sheet, tree.cell),
+                };
+
+                let node = match &tree.json
+                {
+                    Ok(json) => format!("\"tree\": {}", json),
+                    Err(message) => format!("\"error\": {}", json_string(message)),
+                };
+
+                diagnostics.push(format!("{{\"file\": {}, \"cell\": {}, {}}}", json_string(&input_filename), json_string(&cell), node));
+            }
+
+            continue;
+        }
+
+        if validate_only
+        {
+            for diagnostic in evaluator.diagnose(&input)
+            {
+                let cell = match (&diagnostic.cell, diagnostic.sheet.as_str())
+                {
+                    (None, _) => String::from("null"),
+                    (Some(cell), "") => json_string(&cell.to_string()),
+                    (Some(cell), sheet) => json_string(&format!("{}!{}", sheet, cell)),
+                };
+
+                diagnostics.push(format!("{{\"file\": {}, \"cell\": {}, \"kind\": \"{}\", \"message\": {}}}",
+                    json_string(&input_filename), cell, diagnostic.kind, json_string(&diagnostic.message)));
+            }
+
+            continue;
+        }
+
+        // `{name}` is replaced by the input's filename without its extension (e.g. `sales` for `data/sales.txt`)
+        let output_filename = match &output_dir
+        {
+            Some(dir) =>
+            {
+                let name = Path::new(&input_filename).file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+                Path::new(dir).join(output_name.replace("{name}", &name)).display().to_string()
+            },
+            None => output_filename.clone(),
+        };
+
+        if lint
+        {
+            for warning in Sheet::with_options(&input, &load_options).lint()
+            {
+                eprintln!("warning: {}", warning);
+            }
+        }
+
+        let unevaluated;
+        let sheet = if no_eval
+        {
+            unevaluated = Sheet::with_options(&input, &load_options);
+            unevaluated.validate();
+
+            &unevaluated
+        }
+        else
+        {
+            match evaluator.evaluate(&input)
+            {
+                Ok(sheet) => sheet,
+                // The other inputs are still evaluated, like the other cells of this one
+                Err(EvalError::Failures(diagnostics)) =>
+                {
+                    for diagnostic in diagnostics
+                    {
+                        eprintln!("error: {}: {}", input_filename, diagnostic);
+                    }
+
+                    failed = true;
+                    continue;
+                },
+                Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error.to_string())),
+            }
+        };
+
+        // Generating output
+        let start = Instant::now();
+
+        if output_filename == "-"
+        {
+            let stdout = io::stdout();
+
+            // Escape codes would only clutter a file or a pipe
+            options.color = color && stdout.is_terminal();
 
-    input_file.unwrap().read_to_string(&mut input).unwrap();
+            let mut output = io::BufWriter::new(stdout.lock());
+            sheet.write_to(&options, &mut output)?;
+            output.flush()?;
+        }
+        else
+        {
+            let mut output_file = io::BufWriter::new(fs::File::create(&output_filename)?);
+            sheet.write_to(&options, &mut output_file)?;
+            output_file.flush()?;
+        }
 
-    // Generating output file
-    let output = Parser::parse_file(input);
+        let write = start.elapsed();
 
-    let output_filename = "output";
-    let mut output_file = fs::File::create(output_filename).unwrap();
-    output_file.write_all(output.as_bytes()).unwrap();
+        if profile
+        {
+            eprintln!("read:     {:?}", read);
+            eprintln!("{}", evaluator.profile());
+            eprintln!("write:    {:?}", write);
+        }
 
-    println!("Output saved to: {}", output_filename);
+        // The message would be mixed with the output when writing to stdout
+        if !quiet && output_filename != "-"
+        {
+            println!("Output saved to: {}", output_filename);
+        }
+    }
+
+    if ast_json
+    {
+        if diagnostics.is_empty()
+        {
+            println!("[]");
+        }
+        else
+        {
+            println!("[\n  {}\n]", diagnostics.join(",\n  "));
+        }
+    }
+
+    if validate_only
+    {
+        if diagnostics.is_empty()
+        {
+            println!("[]");
+        }
+        else
+        {
+            println!("[\n  {}\n]", diagnostics.join(",\n  "));
+            process::exit(1);
+        }
+    }
+
+    if failed
+    {
+        process::exit(1);
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn columns_by_letter_or_number()
+    {
+        assert_eq!(parse_column("A"), Some(0));
+        assert_eq!(parse_column(" c "), Some(2));
+        assert_eq!(parse_column("AA"), Some(26));
+        assert_eq!(parse_column("2"), Some(2));
+        assert_eq!(parse_column("A2"), None);
+        assert_eq!(parse_column(""), None);
+        assert_eq!(parse_column("ZZZZZZZZZZZZZZZZ"), None);
+    }
+
+    #[test]
+    fn config_file_options_come_before_the_command_line()
+    {
+        let path = env::temp_dir().join(format!("mini-excel-config-{}.toml", process::id()));
+        fs::write(&path, "# Defaults\nround_output = 2\nfail-fast = true\nquiet = false\nnull-value = \"-\" # blanks\n").unwrap();
+
+        let args = config_args(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(args, ["--round-output", "2", "--fail-fast", "--null-value", "-"]);
+    }
+
+    #[test]
+    fn precision_is_round_output()
+    {
+        let path = env::temp_dir().join(format!("mini-excel-precision-config-{}.toml", process::id()));
+        fs::write(&path, "precision = 3\n").unwrap();
+
+        let args = config_args(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(args, ["--round-output", "3"]);
+    }
+
+    #[test]
+    fn tab_width_config_key()
+    {
+        let path = env::temp_dir().join(format!("mini-excel-tab-config-{}.toml", process::id()));
+        fs::write(&path, "tab-width = true\n").unwrap();
+
+        let args = config_args(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(args, ["--tab-width"]);
+    }
+
+    #[test]
+    fn missing_config_file_gives_no_options()
+    {
+        assert!(config_args(Path::new("/nonexistent/.mini-excel.toml")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_config_option_fails()
+    {
+        let path = env::temp_dir().join(format!("mini-excel-invalid-config-{}.toml", process::id()));
+        fs::write(&path, "decimals = 2\n").unwrap();
+
+        let error = config_args(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
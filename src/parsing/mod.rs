@@ -1,15 +1,147 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::Once;
+use std::panic;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use rand::prelude::Rng;
-use crate::scanning::{CellIndex, LiteralValue, Token, TokenType, Tokenizer};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use crate::logging;
+use crate::preprocessing;
+use crate::scanning::{CellIndex, LiteralValue, Token, TokenType, Tokenizer, FUNCTIONS};
 
-type Table = HashMap<CellIndex, Cell>;
+#[cfg(test)]
+mod tests;
+
+const FORMULA_COLOR: &str = "\x1b[36m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+// The value of a formula referring to a cell that doesn't exist, see `Sheet::evaluate_formula`
+const REF_ERROR: &str = "#REF!";
+
+// Numbers this close relative to their size are equal for `=` and `<>` (e.g. `0.1 + 0.2 = 0.3`)
+pub const DEFAULT_EPSILON: f64 = 1e-12;
+
+// Their values fill the cells to the right of their formula
+const SPILLING_FUNCTIONS: &[&str] = &["sequence", "randarray", "runningsum"];
+
+// Their argument may fail without failing them, so it's only evaluated with them, never folded
+const INSPECTING_FUNCTIONS: &[&str] = &["iserror", "isna"];
+
+pub type Table = HashMap<CellIndex, Cell>;
+
+// A function added by an embedder, called with the values of its arguments (ranges give their
+// non-blank values), see `Evaluator::register_function`
+pub type UserFunction = Box<dyn Fn(&[LiteralValue]) -> Result<LiteralValue, EvalError>>;
+pub type FunctionRegistry = HashMap<String, UserFunction>;
 type VisitingList = Vec<CellIndex>;
 
-trait Expression
+// What formulas are evaluated against, the last visited cell is the one being evaluated
+struct Context<'a>
+{
+    expr_cells  : &'a Table,
+    value_cells : &'a mut Table,
+    visiting    : &'a mut VisitingList,
+    force_recalc: bool,
+    epsilon     : f64,
+    sheet_names : &'a [String],
+    rng         : &'a mut StdRng,
+    // With a seed, each formula draws from its own generator seeded from it and the cell, see `Sheet::evaluate_formula`
+    cell_seed   : Option<u64>,
+    functions   : &'a FunctionRegistry,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError
+{
+    // The cells of the cycle in reference order, the first one refers back to itself through the others
+    Cycle(Vec<CellIndex>),
+    // A cell referred to by a formula evaluated on its own, see `eval_expression`
+    NoSheet(CellIndex),
+    // A reference to a cell that isn't in the sheet, not even as a blank cell
+    UnknownCell(CellIndex),
+    // Any other reference that can't be followed (e.g. to a `#REF!` cell)
+    InvalidReference(String),
+    // Every failure of a sheet, when evaluation doesn't stop at the first one, see `Evaluator::set_fail_fast`
+    Failures(Vec<Diagnostic>),
+}
+
+impl fmt::Display for EvalError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            EvalError::Cycle(cells) =>
+            {
+                write!(f, "Cycle detected: ")?;
+
+                for cell in cells
+                {
+                    write!(f, "{} -> ", cell)?;
+                }
+
+                write!(f, "{}", cells[0])
+            },
+            EvalError::NoSheet(cell) => write!(f, "No sheet context to refer to {}", cell),
+            EvalError::UnknownCell(cell) => write!(f, "Referring to an unknown cell {}", cell),
+            EvalError::InvalidReference(message) => write!(f, "{}", message),
+            EvalError::Failures(diagnostics) =>
+            {
+                let lines = diagnostics.iter().map(Diagnostic::to_string).collect::<Vec<String>>();
+                write!(f, "{}", lines.join("\n"))
+            },
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+trait Expression: fmt::Debug
 {
-    fn evaluate(&mut self, expr_cells: &Table, value_cells: &mut Table, visiting: &mut VisitingList) -> LiteralValue;
+    fn evaluate(&mut self, context: &mut Context) -> Result<LiteralValue, EvalError>;
+
+    // Every cell the expression refers to, in the order they're written. With `ranges` the ranges give
+    // all their cells, otherwise only the cells referred to on their own are collected
+    fn collect_refs(&self, out: &mut Vec<CellIndex>, ranges: bool) -> ();
+
+    // The tree of the expression as a JSON object with its `type` (e.g. for an editor), see `Sheet::syntax_trees`
+    fn to_json(&self) -> String;
+
+    // `Some` only when the value doesn't depend on any cell or randomness
+    fn try_eval_const(&self) -> Option<LiteralValue>
+    {
+        None
+    }
+
+    fn cell_index(&self) -> Option<CellIndex>
+    {
+        None
+    }
+
+    // Every cell of a range, blank or not, in row-major order
+    fn range_cells(&self) -> Option<Vec<CellIndex>>
+    {
+        None
+    }
+
+    // Only ranges and array functions evaluate to several values, blank cells are left out
+    fn evaluate_range(&mut self, _context: &mut Context) -> Result<Option<Vec<LiteralValue>>, EvalError>
+    {
+        Ok(None)
+    }
+
+    // Whether the values of `evaluate_range` fill the cells to the right when used as a whole formula
+    fn spills(&self) -> bool
+    {
+        false
+    }
 }
 
+#[derive(Debug)]
 struct Binary(Box<dyn Expression>, Token, Box<dyn Expression>);
 
 impl Binary
@@ -18,20 +150,41 @@ impl Binary
     {
         Binary(left, operator, right)
     }
-}
 
-impl Expression for Binary
-{
-    fn evaluate(&mut self, expr_cells: &Table, value_cells: &mut Table, visiting: &mut VisitingList) -> LiteralValue
+    fn apply(&self, left: LiteralValue, right: LiteralValue, epsilon: f64) -> LiteralValue
     {
-        let left = self.0.evaluate(expr_cells, value_cells, visiting);
-        let right = self.2.evaluate(expr_cells, value_cells, visiting);
+        if let Some(comparison) = Comparison::from_token(self.1.get_type())
+        {
+            return LiteralValue::Float(if comparison.compare(&left, &right, epsilon) { 1.0 } else { 0.0 });
+        }
+
+        // Integers stay exact, they only become floats on an overflow or an inexact result
+        if let (LiteralValue::Int(a), LiteralValue::Int(b)) = (&left, &right)
+        {
+            let (a, b) = (*a, *b);
+
+            let exact = match self.1.get_type()
+            {
+                TokenType::Plus  => a.checked_add(b),
+                TokenType::Minus => a.checked_sub(b),
+                TokenType::Star  => a.checked_mul(b),
+                TokenType::Slash => a.checked_rem(b).filter(|rem| *rem == 0).and_then(|_| a.checked_div(b)),
+                TokenType::Caret => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+                _ => None,
+            };
+
+            if let Some(i) = exact
+            {
+                return LiteralValue::Int(i);
+            }
+        }
 
         let num1 =
         {
             match left
             {
                 LiteralValue::Float(f) => f,
+                LiteralValue::Int(i) => i as f64,
                 _ => panic!("Expected numbers in binary expression")
             }
         };
@@ -41,6 +194,7 @@ impl Expression for Binary
             match right
             {
                 LiteralValue::Float(f) => f,
+                LiteralValue::Int(i) => i as f64,
                 _ => panic!("Expected numbers in binary expression")
             }
         };
@@ -51,12 +205,51 @@ impl Expression for Binary
             TokenType::Minus => LiteralValue::Float(num1 - num2),
             TokenType::Star  => LiteralValue::Float(num1 * num2),
             TokenType::Slash => LiteralValue::Float(num1 / num2),
+            TokenType::Caret => LiteralValue::Float(num1.powf(num2)),
+
+            TokenType::And => LiteralValue::Float(if num1 != 0.0 && num2 != 0.0 { 1.0 } else { 0.0 }),
+            TokenType::Or  => LiteralValue::Float(if num1 != 0.0 || num2 != 0.0 { 1.0 } else { 0.0 }),
 
             _ => panic!("Expected an operator")
         }
     }
 }
 
+impl Expression for Binary
+{
+    fn collect_refs(&self, out: &mut Vec<CellIndex>, ranges: bool) -> ()
+    {
+        self.0.collect_refs(out, ranges);
+        self.2.collect_refs(out, ranges);
+    }
+
+    fn to_json(&self) -> String
+    {
+        format!("{{\"type\": \"binary\", \"operator\": {}, \"left\": {}, \"right\": {}}}",
+            json_string(self.1.get_lexeme()), self.0.to_json(), self.2.to_json())
+    }
+
+    fn evaluate(&mut self, context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        let left = self.0.evaluate(context)?;
+        let right = self.2.evaluate(context)?;
+
+        Ok(self.apply(left, right, context.epsilon))
+    }
+
+    // The epsilon can be changed per evaluation, so equalities are left to it
+    fn try_eval_const(&self) -> Option<LiteralValue>
+    {
+        if matches!(self.1.get_type(), TokenType::Equal | TokenType::NotEqual)
+        {
+            return None;
+        }
+
+        Some(self.apply(self.0.try_eval_const()?, self.2.try_eval_const()?, DEFAULT_EPSILON))
+    }
+}
+
+#[derive(Debug)]
 struct Unary(Token, Box<dyn Expression>);
 
 impl Unary
@@ -65,19 +258,25 @@ impl Unary
     {
         Unary(operator, expression)
     }
-}
 
-impl Expression for Unary
-{
-    fn evaluate(&mut self, expr_cells: &Table, value_cells: &mut Table, visiting: &mut VisitingList) -> LiteralValue
+    fn apply(&self, expression: LiteralValue) -> LiteralValue
     {
-        let expression = self.1.evaluate(expr_cells, value_cells, visiting);
+        if let LiteralValue::Int(i) = expression
+        {
+            match (self.0.get_type(), i.checked_neg())
+            {
+                (TokenType::Plus, _) => return LiteralValue::Int(i),
+                (TokenType::Minus, Some(negated)) => return LiteralValue::Int(negated),
+                _ => (),
+            }
+        }
 
         let num =
         {
             match expression
             {
                 LiteralValue::Float(f) => f,
+                LiteralValue::Int(i) => i as f64,
                 _ => panic!("Expected numbers in binary expression")
             }
         };
@@ -87,400 +286,3461 @@ impl Expression for Unary
         {
             TokenType::Plus  => LiteralValue::Float(num),
             TokenType::Minus => LiteralValue::Float(-num),
+            TokenType::Bang  => LiteralValue::Float(if num == 0.0 { 1.0 } else { 0.0 }),
 
-            _ => panic!("Expected '+' or '-' operator"),
+            _ => panic!("Expected '+', '-' or '!' operator"),
         }
     }
 }
 
-struct FnExpression(String, Vec<Box<dyn Expression>>);
-
-impl FnExpression
+impl Expression for Unary
 {
-    pub fn new(name: String, params: Vec<Box<dyn Expression>>) -> Self
+    fn collect_refs(&self, out: &mut Vec<CellIndex>, ranges: bool) -> ()
+    {
+        self.1.collect_refs(out, ranges);
+    }
+
+    fn to_json(&self) -> String
+    {
+        format!("{{\"type\": \"unary\", \"operator\": {}, \"operand\": {}}}", json_string(self.0.get_lexeme()), self.1.to_json())
+    }
+
+    fn evaluate(&mut self, context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        let expression = self.1.evaluate(context)?;
+
+        Ok(self.apply(expression))
+    }
+
+    fn try_eval_const(&self) -> Option<LiteralValue>
     {
-        FnExpression(name, params)
+        Some(self.apply(self.1.try_eval_const()?))
     }
 }
 
-impl Expression for FnExpression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison
+{
+    Equal, NotEqual,
+    Less, LessEqual,
+    Greater, GreaterEqual,
+}
+
+impl Comparison
 {
-    fn evaluate(&mut self, expr_cells: &Table, value_cells: &mut Table, visiting: &mut VisitingList) -> LiteralValue
+    pub fn from_token(t: &TokenType) -> Option<Self>
     {
-        match self.0.as_str()
+        match t
         {
-            "random" =>
-            {
-                if !self.1.is_empty()
-                {
-                    panic!("Function `random` doesn't take any arguments");
-                }
+            TokenType::Equal        => Some(Comparison::Equal),
+            TokenType::NotEqual     => Some(Comparison::NotEqual),
+            TokenType::Less         => Some(Comparison::Less),
+            TokenType::LessEqual    => Some(Comparison::LessEqual),
+            TokenType::Greater      => Some(Comparison::Greater),
+            TokenType::GreaterEqual => Some(Comparison::GreaterEqual),
+            _ => None,
+        }
+    }
 
-                return LiteralValue::Float(rand::thread_rng().gen::<i32>() as f32);
-            },
-            "randbetween" =>
-            {
-                if self.1.len() != 2
-                {
-                    panic!("Function `randbetween` takes only 2 arguments");
-                }
+    pub fn holds(&self, ordering: Ordering) -> bool
+    {
+        match self
+        {
+            Comparison::Equal        => ordering == Ordering::Equal,
+            Comparison::NotEqual     => ordering != Ordering::Equal,
+            Comparison::Less         => ordering == Ordering::Less,
+            Comparison::LessEqual    => ordering != Ordering::Greater,
+            Comparison::Greater      => ordering == Ordering::Greater,
+            Comparison::GreaterEqual => ordering != Ordering::Less,
+        }
+    }
 
-                let num1 =
-                {
-                    match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                    {
-                        LiteralValue::Float(f) => f,
-                        _ => panic!("Expected numbers as `randbetween` params")
-                    }
-                };
+    // Texts are compared ignoring case, a number and a text are never equal.
+    // Only `=` and `<>` allow numbers to differ by `epsilon`, the orderings stay exact
+    pub fn compare(&self, left: &LiteralValue, right: &LiteralValue, epsilon: f64) -> bool
+    {
+        let ordering = match (left, right)
+        {
+            // Integers are compared exactly, against a float as a float
+            (LiteralValue::Int(a), LiteralValue::Int(b)) => Some(a.cmp(b)),
+            (LiteralValue::Int(a), _) => return self.compare(&LiteralValue::Float(*a as f64), right, epsilon),
+            (_, LiteralValue::Int(b)) => return self.compare(left, &LiteralValue::Float(*b as f64), epsilon),
+            (LiteralValue::Float(a), LiteralValue::Float(b))
+                if matches!(self, Comparison::Equal | Comparison::NotEqual) && (a - b).abs() <= epsilon * a.abs().max(b.abs()) =>
+                Some(Ordering::Equal),
+            (LiteralValue::Float(a), LiteralValue::Float(b)) => a.partial_cmp(b),
+            (LiteralValue::Text(a), LiteralValue::Text(b)) => Some(a.to_lowercase().cmp(&b.to_lowercase())),
+            _ => None,
+        };
 
-                let num2 =
-                {
-                    match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                    {
-                        LiteralValue::Float(f) => f,
-                        _ => panic!("Expected numbers as `randbetween` params")
-                    }
-                };
+        match (ordering, self)
+        {
+            (Some(ordering), _) => self.holds(ordering),
+            (None, Comparison::Equal) => false,
+            (None, Comparison::NotEqual) => true,
+            (None, _) => panic!("Cannot order `{:?}` against `{:?}`", left, right),
+        }
+    }
+}
 
-                if num1 >= num2
-                {
-                    panic!("First argument in `randbetween` should be smaller that the second");
-                }
+struct Criterion(Comparison, LiteralValue);
 
-                return LiteralValue::Float(rand::thread_rng().gen_range(num1..num2));
-            },
-            "sum" =>
-            {
-                let mut sum = 0.0;
+impl Criterion
+{
+    // A number matches equal values, a text may start with one of `=`, `<>`, `<`, `<=`, `>`, `>=`
+    pub fn new(criterion: LiteralValue) -> Self
+    {
+        let text = match criterion
+        {
+            LiteralValue::Text(text) => text,
+            LiteralValue::Int(i) => return Criterion(Comparison::Equal, LiteralValue::Float(i as f64)),
+            _ => return Criterion(Comparison::Equal, criterion),
+        };
 
-                while !self.1.is_empty()
-                {
-                    let num =
-                    {
-                        match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                        {
-                            LiteralValue::Float(f) => f,
-                            _ => panic!("Expected numbers as `sum` params")
-                        }
-                    };
+        let (comparison, operand) =
+            [("<>", Comparison::NotEqual), ("<=", Comparison::LessEqual), (">=", Comparison::GreaterEqual),
+             ("<", Comparison::Less), (">", Comparison::Greater), ("=", Comparison::Equal)]
+            .iter()
+            .find(|(operator, _)| text.starts_with(operator))
+            .map(|(operator, comparison)| (*comparison, &text[operator.len()..]))
+            .unwrap_or((Comparison::Equal, &text));
 
-                    sum += num;
-                }
+        if operand.starts_with(['<', '>', '='])
+        {
+            panic!("Malformed criterion: `{}`, expected one of `=`, `<>`, `<`, `<=`, `>`, `>=` followed by a value (e.g. `>=5`)", text);
+        }
+
+        match operand.trim().parse::<f64>()
+        {
+            Ok(f) => Criterion(comparison, LiteralValue::Float(f)),
+            Err(_) if comparison == Comparison::Equal || comparison == Comparison::NotEqual =>
+                Criterion(comparison, LiteralValue::Text(operand.to_string())),
+            Err(_) => panic!("Malformed criterion: `{}`, only numbers can be ordered", text),
+        }
+    }
 
-                return LiteralValue::Float(sum);
+    pub fn matches(&self, value: &LiteralValue) -> bool
+    {
+        match (&self.1, value)
+        {
+            (LiteralValue::Float(expected), LiteralValue::Float(f)) =>
+            {
+                f.partial_cmp(expected).is_some_and(|ordering| self.0.holds(ordering))
             },
-            "average" =>
+            (LiteralValue::Float(_), LiteralValue::Int(i)) => self.matches(&LiteralValue::Float(*i as f64)),
+            (LiteralValue::Text(expected), LiteralValue::Text(text)) =>
             {
-                if self.1.is_empty()
-                {
-                    panic!("Function `max` expect at least one argument");
-                }
-
-                let len = self.1.len();
-                let mut sum = 0.0;
+                (self.0 == Comparison::Equal) == expected.eq_ignore_ascii_case(text)
+            },
+            _ => self.0 == Comparison::NotEqual,
+        }
+    }
+}
 
-                while !self.1.is_empty()
-                {
-                    sum +=
-                    {
-                        match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                        {
-                            LiteralValue::Float(f) => f,
-                            _ => panic!("Expected numbers as `sum` params")
-                        }
-                    };
-                }
+// Dates are serials counting days since 1899-12-30, like spreadsheets do for dates after 1900-02-28
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Date
+{
+    year : i64,
+    month: i64,
+    day  : i64,
+}
 
-                return LiteralValue::Float(sum/(len as f32));
-            },
-            "max" =>
-            {
-                if self.1.is_empty()
-                {
-                    panic!("Function `max` expect at least one argument");
-                }
+impl Date
+{
+    // Days between 1899-12-30 and 1970-01-01
+    const UNIX_EPOCH_SERIAL: i64 = 25569;
 
-                let mut max =
-                {
-                    match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                    {
-                        LiteralValue::Float(f) => f,
-                        _ => panic!("Expected numbers as `max` params")
-                    }
-                };
+    // Out of range months and days carry over (e.g. month 13 is January of the next year)
+    pub fn serial(year: i64, month: i64, day: i64) -> i64
+    {
+        let year = year + (month - 1).div_euclid(12);
+        let month = (month - 1).rem_euclid(12) + 1;
 
-                while !self.1.is_empty()
-                {
-                    let num =
-                    {
-                        match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                        {
-                            LiteralValue::Float(f) => f,
-                            _ => panic!("Expected numbers as `max` params")
-                        }
-                    };
+        // Days since 1970-01-01 of the first of the month, counting years from March
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let year_of_era = y - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
 
-                    if num > max
-                    {
-                        max = num;
-                    }
-                }
+        era * 146097 + day_of_era - 719468 + Date::UNIX_EPOCH_SERIAL + day - 1
+    }
 
-                return LiteralValue::Float(max);
-            },
-            "min" =>
-            {
-                if self.1.is_empty()
-                {
-                    panic!("Function `min` expect at least one argument");
-                }
+    pub fn from_serial(serial: f64) -> Self
+    {
+        let days = serial.floor() as i64 - Date::UNIX_EPOCH_SERIAL + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+
+        Date
+        {
+            year : year_of_era + era * 400 + if month <= 2 { 1 } else { 0 },
+            month,
+            day  : day_of_year - (153 * shifted_month + 2) / 5 + 1,
+        }
+    }
 
-                let mut min =
-                {
-                    match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                    {
-                        LiteralValue::Float(f) => f,
-                        _ => panic!("Expected numbers as `min` params")
-                    }
-                };
+    pub fn today() -> i64
+    {
+        let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
 
-                while !self.1.is_empty()
-                {
-                    let num =
-                    {
-                        match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                        {
-                            LiteralValue::Float(f) => f,
-                            _ => panic!("Expected numbers as `min` params")
-                        }
-                    };
+        seconds as i64 / 86400 + Date::UNIX_EPOCH_SERIAL
+    }
+}
 
-                    if num < min
-                    {
-                        min = num;
-                    }
-                }
+// A small subset of spreadsheet number formats: `0`, `000`, `0.00`, `0%`, `0.0%`, `$0.00`,
+// `#` stands for an integer digit that isn't zero-padded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat
+{
+    integer_digits: usize,
+    decimals      : usize,
+    percent       : bool,
+    currency      : bool,
+}
 
-                return LiteralValue::Float(min);
-            },
-            "if" =>
-            {
-                if self.1.len() != 3
-                {
-                    panic!("Function `if` takes only 3 arguments");
-                }
+impl NumberFormat
+{
+    pub fn parse(format: &str) -> Option<Self>
+    {
+        let (format, currency) = match format.strip_prefix('$')
+        {
+            Some(format) => (format, true),
+            None => (format, false),
+        };
 
-                let first =
-                {
-                    match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                    {
-                        LiteralValue::Float(f) => f,
-                        _ => panic!("Expected numbers as `if` params")
-                    }
-                };
+        let (format, percent) = match format.strip_suffix('%')
+        {
+            Some(format) => (format, true),
+            None => (format, false),
+        };
 
-                let second =
+        let (integer, decimals) = match format.split_once('.')
+        {
+            Some((integer, decimals)) => (integer, Some(decimals)),
+            None => (format, None),
+        };
+
+        if integer.is_empty() || !integer.chars().all(|c| c == '0' || c == '#')
+        {
+            return None;
+        }
+
+        if decimals.is_some_and(|decimals| decimals.is_empty() || !decimals.chars().all(|c| c == '0'))
+        {
+            return None;
+        }
+
+        Some(NumberFormat
+        {
+            integer_digits: integer.chars().filter(|c| *c == '0').count(),
+            decimals      : decimals.map_or(0, str::len),
+            percent,
+            currency,
+        })
+    }
+
+    pub fn format(&self, value: f64) -> String
+    {
+        let value = if self.percent { value * 100.0 } else { value };
+        let digits = format!("{:.*}", self.decimals, value.abs());
+        let integer_len = digits.find('.').unwrap_or(digits.len());
+
+        let mut formatted = String::new();
+
+        // A value rounded to zero doesn't keep its sign
+        if value < 0.0 && digits.chars().any(|c| c.is_ascii_digit() && c != '0')
+        {
+            formatted.push('-');
+        }
+
+        if self.currency
+        {
+            formatted.push('$');
+        }
+
+        formatted.push_str(&"0".repeat(self.integer_digits.saturating_sub(integer_len)));
+        formatted.push_str(&digits);
+
+        if self.percent
+        {
+            formatted.push('%');
+        }
+
+        formatted
+    }
+}
+
+#[derive(Debug)]
+struct FnExpression(String, Vec<Box<dyn Expression>>);
+
+impl FnExpression
+{
+    // Names are matched ignoring case (e.g. `SUM` or `Sum`), so they're kept lowercase
+    pub fn new(name: String, params: Vec<Box<dyn Expression>>) -> Self
+    {
+        FnExpression(name.to_ascii_lowercase(), params)
+    }
+
+    // Functions whose result depends on randomness, on the cell being evaluated or on its sheet
+    fn is_volatile(&self) -> bool
+    {
+        ["random", "randbetween", "randarray", "today", "row", "column", "rows", "columns", "offset", "indirect"].contains(&self.0.as_str())
+    }
+
+    // Panics with the same message for every function getting too few or too many arguments,
+    // `max` is `None` for functions taking any number of arguments
+    fn expect_args(&self, min: usize, max: Option<usize>) -> ()
+    {
+        let count = self.1.len();
+
+        if count >= min && max.is_none_or(|max| count <= max)
+        {
+            return;
+        }
+
+        let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+
+        let expected = match max
+        {
+            Some(0) => String::from("no arguments"),
+            Some(max) if max == min => format!("{} {}", min, plural(min)),
+            Some(max) => format!("{} to {} {}", min, max, plural(max)),
+            None => format!("at least {} {}", min, plural(min)),
+        };
+
+        panic!("Function `{}` expects {}, got {}", self.0, expected, count);
+    }
+
+    fn next_number(&mut self, context: &mut Context) -> Result<f64, EvalError>
+    {
+        match self.1.remove(0).evaluate(context)?
+        {
+            LiteralValue::Float(f) => Ok(f),
+            LiteralValue::Int(i) => Ok(i as f64),
+            _ => panic!("Expected numbers as `{}` params", self.0)
+        }
+    }
+
+    // A non-negative integer (e.g. a count of items), kept as `f64` so that large results don't overflow
+    fn next_count(&mut self, context: &mut Context) -> Result<f64, EvalError>
+    {
+        let n = self.next_number(context)?;
+
+        if n < 0.0 || n.fract() != 0.0
+        {
+            panic!("Function `{}` expects non-negative integers, got `{}`", self.0, n);
+        }
+
+        Ok(n)
+    }
+
+    // Numbers are taken as their displayed text
+    fn next_text(&mut self, context: &mut Context) -> Result<String, EvalError>
+    {
+        match self.1.remove(0).evaluate(context)?
+        {
+            LiteralValue::Text(text) => Ok(text),
+            LiteralValue::Float(f) => Ok(Cell::number(f)),
+            LiteralValue::Int(i) => Ok(i.to_string()),
+            _ => panic!("Expected text as `{}` params", self.0)
+        }
+    }
+
+    // Consumes all params as texts, blank cells of ranges are left out unless `keep_blanks`
+    fn texts(&mut self, context: &mut Context, keep_blanks: bool) -> Result<Vec<String>, EvalError>
+    {
+        let mut texts = Vec::<String>::new();
+
+        while !self.1.is_empty()
+        {
+            let mut param = self.1.remove(0);
+
+            let values = match param.range_cells()
+            {
+                Some(cells) if keep_blanks =>
+                {
+                    let mut values = Vec::<LiteralValue>::new();
+
+                    for cell_index in cells
+                    {
+                        values.push(if CellRef::is_blank(&cell_index, context)
+                            { LiteralValue::Text(String::new()) } else { CellRef::resolve(cell_index, context)? });
+                    }
+
+                    values
+                },
+                _ => match param.evaluate_range(context)?
+                {
+                    Some(values) => values,
+                    None => vec![param.evaluate(context)?],
+                },
+            };
+
+            for value in values
+            {
+                texts.push(match value
+                {
+                    LiteralValue::Text(text) => text,
+                    LiteralValue::Float(f) => Cell::number(f),
+                    LiteralValue::Int(i) => i.to_string(),
+                    _ => panic!("Expected text as `{}` params", self.0)
+                });
+            }
+        }
+
+        Ok(texts)
+    }
+
+    // Consumes all params, a number param must be a number while ranges skip whatever isn't
+    fn numbers(&mut self, context: &mut Context) -> Result<Vec<f64>, EvalError>
+    {
+        let mut numbers = Vec::<f64>::new();
+
+        while !self.1.is_empty()
+        {
+            let mut param = self.1.remove(0);
+
+            match param.evaluate_range(context)?
+            {
+                Some(values) =>
+                {
+                    numbers.extend(values.into_iter().filter_map(|value|
+                        match value
+                        {
+                            LiteralValue::Float(f) => Some(f),
+                            LiteralValue::Int(i) => Some(i as f64),
+                            _ => None
+                        }));
+                },
+                None =>
+                {
+                    match param.evaluate(context)?
+                    {
+                        LiteralValue::Float(f) => numbers.push(f),
+                        LiteralValue::Int(i) => numbers.push(i as f64),
+                        _ => panic!("Expected numbers as `{}` params", self.0)
+                    }
+                }
+            }
+        }
+
+        Ok(numbers)
+    }
+
+    // Welford's streaming mean, the running mean stays the size of the numbers instead of
+    // their sum which loses precision (or overflows) for long lists of large numbers
+    fn mean(numbers: &[f64]) -> f64
+    {
+        numbers.iter().enumerate().fold(0.0, |mean, (i, n)| mean + (n - mean) / ((i + 1) as f64))
+    }
+}
+
+impl Expression for FnExpression
+{
+    fn collect_refs(&self, out: &mut Vec<CellIndex>, ranges: bool) -> ()
+    {
+        for param in &self.1
+        {
+            param.collect_refs(out, ranges);
+        }
+    }
+
+    fn to_json(&self) -> String
+    {
+        let args = self.1.iter().map(|param| param.to_json()).collect::<Vec<String>>();
+
+        format!("{{\"type\": \"function\", \"name\": {}, \"args\": [{}]}}", json_string(&self.0), args.join(", "))
+    }
+
+    fn try_eval_const(&self) -> Option<LiteralValue>
+    {
+        // Registered functions are only known while evaluating
+        if self.is_volatile() || self.spills() || INSPECTING_FUNCTIONS.contains(&self.0.as_str()) || !FUNCTIONS.contains(&self.0.as_str())
+        {
+            return None;
+        }
+
+        let mut params = Vec::<Box<dyn Expression>>::new();
+
+        for param in &self.1
+        {
+            params.push(Box::new(Literal::from_value(param.try_eval_const()?)));
+        }
+
+        let context = &mut Context
+        {
+            expr_cells  : &HashMap::new(),
+            value_cells : &mut HashMap::new(),
+            visiting    : &mut vec![],
+            force_recalc: false,
+            epsilon     : DEFAULT_EPSILON,
+            sheet_names : &[],
+            // Never drawn from, functions using it are volatile
+            rng         : &mut StdRng::seed_from_u64(0),
+            cell_seed   : None,
+            functions   : &HashMap::new(),
+        };
+
+        FnExpression::new(self.0.clone(), params).evaluate(context).ok()
+    }
+
+    fn spills(&self) -> bool
+    {
+        SPILLING_FUNCTIONS.contains(&self.0.as_str())
+    }
+
+    fn evaluate_range(&mut self, context: &mut Context) -> Result<Option<Vec<LiteralValue>>, EvalError>
+    {
+        if !self.spills()
+        {
+            return Ok(None);
+        }
+
+        // The total after each number, ranges give one number per non-blank cell
+        if self.0 == "runningsum"
+        {
+            self.expect_args(1, None);
+
+            let numbers = self.numbers(context)?;
+
+            if numbers.is_empty()
+            {
+                panic!("Function `runningsum` has no numbers to add up");
+            }
+
+            let mut total = 0.0;
+
+            return Ok(Some(numbers.into_iter().map(|n| { total += n; LiteralValue::Float(total) }).collect()));
+        }
+
+        self.expect_args(1, Some(1));
+
+        let count = self.next_number(context)?.trunc();
+
+        if count < 1.0
+        {
+            panic!("Function `{}` expects a count of at least 1", self.0);
+        }
+
+        let values = match self.0.as_str()
+        {
+            "sequence" => (1..=count as usize).map(|n| LiteralValue::Float(n as f64)).collect(),
+            // Like `random`, values in [0, 1) that follow `--seed` when it's given
+            _ => (0..count as usize).map(|_| LiteralValue::Float(context.rng.gen::<f64>())).collect(),
+        };
+
+        Ok(Some(values))
+    }
+
+    fn evaluate(&mut self, context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        if let Some(function) = context.functions.get(&self.0)
+        {
+            let mut args = Vec::<LiteralValue>::new();
+
+            for mut param in self.1.drain(..)
+            {
+                match param.evaluate_range(context)?
+                {
+                    Some(values) => args.extend(values),
+                    None => args.push(param.evaluate(context)?),
+                }
+            }
+
+            return function(&args);
+        }
+
+        match self.0.as_str()
+        {
+            "random" =>
+            {
+                self.expect_args(0, Some(0));
+
+                return Ok(LiteralValue::Float(context.rng.gen::<i32>() as f64));
+            },
+            "randbetween" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let num1 =
                 {
-                    match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
+                    match self.1.remove(0).evaluate(context)?
                     {
                         LiteralValue::Float(f) => f,
-                        _ => panic!("Expected numbers as `if` params")
+                        LiteralValue::Int(i) => i as f64,
+                        _ => panic!("Expected numbers as `randbetween` params")
+                    }
+                };
+
+                let num2 =
+                {
+                    match self.1.remove(0).evaluate(context)?
+                    {
+                        LiteralValue::Float(f) => f,
+                        LiteralValue::Int(i) => i as f64,
+                        _ => panic!("Expected numbers as `randbetween` params")
                     }
                 };
 
-                let third =
+                if num1 >= num2
+                {
+                    panic!("First argument in `randbetween` should be smaller that the second");
+                }
+
+                return Ok(LiteralValue::Float(context.rng.gen_range(num1..num2)));
+            },
+            "sum" =>
+            {
+                let numbers = self.numbers(context)?;
+
+                // Summing floats starts from `-0`, so an empty range would give `-0`
+                return Ok(LiteralValue::Float(numbers.iter().fold(0.0, |sum, n| sum + n)));
+            },
+            "average" =>
+            {
+                self.expect_args(1, None);
+
+                let numbers = self.numbers(context)?;
+
+                if numbers.is_empty()
+                {
+                    panic!("Function `average` has no numbers to average");
+                }
+
+                return Ok(LiteralValue::Float(FnExpression::mean(&numbers)));
+            },
+            "sumsq" | "averagesq" =>
+            {
+                self.expect_args(1, None);
+
+                let squares = self.numbers(context)?.iter().map(|n| n * n).collect::<Vec<f64>>();
+
+                if self.0 == "sumsq"
+                {
+                    return Ok(LiteralValue::Float(squares.iter().fold(0.0, |sum, n| sum + n)));
+                }
+
+                if squares.is_empty()
+                {
+                    panic!("Function `averagesq` has no numbers to average");
+                }
+
+                return Ok(LiteralValue::Float(FnExpression::mean(&squares)));
+            },
+            "weightedaverage" =>
+            {
+                self.expect_args(2, None);
+
+                if !self.1.len().is_multiple_of(2)
+                {
+                    panic!("Function `weightedaverage` expects pairs of a value and its weight, got {} arguments", self.1.len());
+                }
+
+                let (mut total, mut total_weight) = (0.0, 0.0);
+
+                while !self.1.is_empty()
+                {
+                    let value = self.next_number(context)?;
+                    let weight = self.next_number(context)?;
+
+                    total += value * weight;
+                    total_weight += weight;
+                }
+
+                if total_weight == 0.0
+                {
+                    panic!("Function `weightedaverage` got a total weight of 0");
+                }
+
+                return Ok(LiteralValue::Float(total / total_weight));
+            },
+            "percentile" | "quartile" =>
+            {
+                self.expect_args(2, None);
+
+                let k = self.next_number(context)?;
+
+                let k = match self.0.as_str()
+                {
+                    "percentile" if (0.0..=1.0).contains(&k) => k,
+                    "quartile" if (0.0..=4.0).contains(&k) && k.fract() == 0.0 => k / 4.0,
+                    _ => panic!("Function `{}` got `{}` out of bounds, expected {}", self.0, k,
+                        if self.0 == "percentile" { "0..1" } else { "an integer in 0..4" }),
+                };
+
+                let mut numbers = self.numbers(context)?;
+
+                if numbers.is_empty()
+                {
+                    panic!("Function `{}` has no numbers", self.0);
+                }
+
+                numbers.sort_by(f64::total_cmp);
+
+                // Like spreadsheets, interpolates between the two closest ranks
+                let rank = k * (numbers.len() - 1) as f64;
+                let (lower, upper) = (numbers[rank.floor() as usize], numbers[rank.ceil() as usize]);
+
+                return Ok(LiteralValue::Float(lower + (upper - lower) * rank.fract()));
+            },
+            // `greatest` and `least` are the SQL names of `max` and `min`
+            "max" | "greatest" =>
+            {
+                self.expect_args(1, None);
+
+                // Like spreadsheets, ranges without any number give 0
+                let numbers = self.numbers(context)?;
+
+                return Ok(LiteralValue::Float(numbers.into_iter().reduce(f64::max).unwrap_or_default()));
+            },
+            "min" | "least" =>
+            {
+                self.expect_args(1, None);
+
+                let numbers = self.numbers(context)?;
+
+                return Ok(LiteralValue::Float(numbers.into_iter().reduce(f64::min).unwrap_or_default()));
+            },
+            // Keeps the first argument between the least and the greatest of the others, so the bounds can
+            // be in any order (`clamp(x, 10, 0)`) or a range (`clamp(x, A0:A9)` stays within its values)
+            "clamp" =>
+            {
+                self.expect_args(2, None);
+
+                let value = self.next_number(context)?;
+                let bounds = self.numbers(context)?;
+
+                // `f64::clamp` would fail on it with its own message
+                if let Some(bound) = bounds.iter().find(|bound| bound.is_nan())
+                {
+                    panic!("Function `clamp` got `{}` as a bound, expected a number", bound);
+                }
+
+                let (Some(least), Some(greatest)) = (bounds.iter().copied().reduce(f64::min), bounds.iter().copied().reduce(f64::max)) else
+                {
+                    panic!("Function `clamp` has no bounds");
+                };
+
+                return Ok(LiteralValue::Float(value.clamp(least, greatest)));
+            },
+            "maxifs" | "minifs" =>
+            {
+                self.expect_args(2, None);
+
+                let criterion = Criterion::new(self.1.remove(0).evaluate(context)?);
+
+                let numbers = self.numbers(context)?
+                    .into_iter()
+                    .filter(|f| criterion.matches(&LiteralValue::Float(*f)));
+
+                // Like spreadsheets, no matching value gives 0
+                let extreme = if self.0 == "maxifs" { numbers.reduce(f64::max) } else { numbers.reduce(f64::min) };
+
+                return Ok(LiteralValue::Float(extreme.unwrap_or_default()));
+            },
+            "sumif" | "countif" =>
+            {
+                self.expect_args(2, Some(if self.0 == "sumif" { 3 } else { 2 }));
+
+                let cells = self.1.remove(0).range_cells()
+                    .unwrap_or_else(|| panic!("Function `{}` expects a range as its first argument", self.0));
+
+                let criterion = Criterion::new(self.1.remove(0).evaluate(context)?);
+
+                // The cells added up by `sumif` are those of the sum range at the same positions
+                let summed = match self.1.pop()
+                {
+                    Some(param) =>
+                    {
+                        let summed = param.range_cells()
+                            .unwrap_or_else(|| panic!("Function `sumif` expects a range as its third argument"));
+
+                        if summed.len() != cells.len()
+                        {
+                            panic!("Function `sumif` expects a sum range of {} cells, got {}", cells.len(), summed.len());
+                        }
+
+                        summed
+                    },
+                    None => cells.clone(),
+                };
+
+                let (mut total, mut count) = (0.0, 0);
+
+                for (cell_index, summed_index) in cells.into_iter().zip(summed)
+                {
+                    // Like in ranges, blank cells are skipped rather than taken as 0
+                    if CellRef::is_blank(&cell_index, context) || !criterion.matches(&CellRef::resolve(cell_index, context)?)
+                    {
+                        continue;
+                    }
+
+                    count += 1;
+
+                    if !CellRef::is_blank(&summed_index, context)
+                    {
+                        match CellRef::resolve(summed_index, context)?
+                        {
+                            LiteralValue::Float(f) => total += f,
+                            LiteralValue::Int(i) => total += i as f64,
+                            _ => (),
+                        }
+                    }
+                }
+
+                return Ok(LiteralValue::Float(if self.0 == "sumif" { total } else { count as f64 }));
+            },
+            "if" =>
+            {
+                self.expect_args(3, Some(3));
+
+                let first =
+                {
+                    match self.1.remove(0).evaluate(context)?
+                    {
+                        LiteralValue::Float(f) => f,
+                        LiteralValue::Int(i) => i as f64,
+                        _ => panic!("Expected numbers as `if` params")
+                    }
+                };
+
+                let second =
+                {
+                    match self.1.remove(0).evaluate(context)?
+                    {
+                        LiteralValue::Float(f) => f,
+                        LiteralValue::Int(i) => i as f64,
+                        _ => panic!("Expected numbers as `if` params")
+                    }
+                };
+
+                let third =
+                {
+                    match self.1.remove(0).evaluate(context)?
+                    {
+                        LiteralValue::Float(f) => f,
+                        LiteralValue::Int(i) => i as f64,
+                        _ => panic!("Expected numbers as `if` params")
+                    }
+                };
+
+                Ok(LiteralValue::Float(if first != 0.0 { second } else { third }))
+            },
+            "row" | "column" =>
+            {
+                self.expect_args(0, Some(0));
+
+                // The last visited cell is the one whose formula is being evaluated,
+                // so a referenced cell reports its own position, not the referencing one
+                let (row, column) = context.visiting
+                    .last()
+                    .expect("Function `row`/`column` can only be used inside a cell")
+                    .get();
+
+                return Ok(LiteralValue::Float((if self.0 == "row" { row } else { column } + 1) as f64));
+            },
+            "rows" | "columns" =>
+            {
+                self.expect_args(0, Some(0));
+
+                let sheet = context.visiting
+                    .last()
+                    .expect("Function `rows`/`columns` can only be used inside a cell")
+                    .sheet();
+
+                // Like `Sheet::dimensions`, the bounding rectangle of the formula's sheet,
+                // blank cells within it are counted too
+                let (rows, columns) = context.expr_cells.keys()
+                    .chain(context.value_cells.keys())
+                    .filter(|index| index.sheet() == sheet)
+                    .fold((0, 0), |(rows, columns), index|
+                    {
+                        let (row, column) = index.get();
+                        (rows.max(row + 1), columns.max(column + 1))
+                    });
+
+                return Ok(LiteralValue::Float((if self.0 == "rows" { rows } else { columns }) as f64));
+            },
+            "offset" =>
+            {
+                self.expect_args(3, Some(3));
+
+                let base = self.1.remove(0).cell_index()
+                    .expect("Expected a cell reference as the first `offset` param");
+
+                let rows =
+                {
+                    match self.1.remove(0).evaluate(context)?
+                    {
+                        LiteralValue::Float(f) => f.trunc() as isize,
+                        LiteralValue::Int(i) => i as isize,
+                        _ => panic!("Expected numbers as `offset` params")
+                    }
+                };
+
+                let columns =
+                {
+                    match self.1.remove(0).evaluate(context)?
+                    {
+                        LiteralValue::Float(f) => f.trunc() as isize,
+                        LiteralValue::Int(i) => i as isize,
+                        _ => panic!("Expected numbers as `offset` params")
+                    }
+                };
+
+                let (row, column) = base.get();
+
+                let (row, column) =
+                    match (row.checked_add_signed(rows), column.checked_add_signed(columns))
+                    {
+                        (Some(row), Some(column)) => (row, column),
+                        _ => return Err(EvalError::InvalidReference(String::from("Function `offset` points before the first row or column"))),
+                    };
+
+                return CellRef::resolve(CellIndex::new(row, column).with_sheet(base.sheet()), context);
+            },
+            "indirect" =>
+            {
+                self.expect_args(1, Some(1));
+
+                let label =
+                {
+                    match self.1.remove(0).evaluate(context)?
+                    {
+                        LiteralValue::Text(text) => text,
+                        _ => panic!("Expected text as `indirect` param")
+                    }
+                };
+
+                // Like references, the label may name another sheet (e.g. `Sheet2!A1`)
+                let (sheet, cell_label) = match label.trim().split_once('!')
+                {
+                    Some((name, cell_label)) =>
+                    {
+                        let sheet = context.sheet_names.iter()
+                            .position(|sheet_name| !sheet_name.is_empty() && sheet_name.eq_ignore_ascii_case(name))
+                            .unwrap_or_else(|| panic!("Function `indirect` refers to an unknown sheet: `{}`", name));
+
+                        (sheet, cell_label)
+                    },
+                    None => (context.visiting.last().map_or(0, |cell| cell.sheet()), label.trim()),
+                };
+
+                let target = CellIndex::from_label(cell_label)
+                    .unwrap_or_else(|| panic!("Function `indirect` expects a cell reference, got: `{}`", label))
+                    .with_sheet(sheet);
+
+                // An unknown cell is a `#REF!` like for a reference
+                return CellRef::resolve(target, context);
+            },
+            "substitute" =>
+            {
+                self.expect_args(3, Some(3));
+
+                let text = self.next_text(context)?;
+                let old = self.next_text(context)?;
+                let new = self.next_text(context)?;
+
+                if old.is_empty()
+                {
+                    return Ok(LiteralValue::Text(text));
+                }
+
+                return Ok(LiteralValue::Text(text.replace(&old, &new)));
+            },
+            "replace" =>
+            {
+                self.expect_args(4, Some(4));
+
+                let text = self.next_text(context)?.chars().collect::<Vec<char>>();
+                let start = self.next_number(context)?;
+                let len = self.next_number(context)?;
+                let new = self.next_text(context)?;
+
+                // `start` is 1-based, positions outside of the text are clamped to it
+                let start = ((start.trunc().max(1.0) as usize) - 1).min(text.len());
+                let end = (start + len.trunc().max(0.0) as usize).min(text.len());
+
+                let mut replaced = text[..start].iter().collect::<String>();
+                replaced.push_str(&new);
+                replaced.extend(&text[end..]);
+
+                return Ok(LiteralValue::Text(replaced));
+            },
+            "find" | "search" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let needle = self.next_text(context)?.chars().collect::<Vec<char>>();
+                let haystack = self.next_text(context)?.chars().collect::<Vec<char>>();
+
+                // `find` is case-sensitive while `search` isn't
+                let case_sensitive = self.0 == "find";
+                let matches = |a: &char, b: &char| if case_sensitive { a == b } else { a.to_lowercase().eq(b.to_lowercase()) };
+
+                let position = (0..(haystack.len() + 1).saturating_sub(needle.len()))
+                    .find(|&i| needle.iter().zip(&haystack[i..]).all(|(a, b)| matches(a, b)))
+                    .unwrap_or_else(|| panic!("Function `{}` didn't find `{}` in `{}`", self.0,
+                        needle.iter().collect::<String>(), haystack.iter().collect::<String>()));
+
+                return Ok(LiteralValue::Float((position + 1) as f64));
+            },
+            "round" | "roundeven" =>
+            {
+                self.expect_args(1, Some(2));
+
+                let number = self.next_number(context)?;
+                let digits = if self.1.is_empty() { 0 } else { self.next_number(context)?.trunc() as i32 };
+                let factor = 10f64.powi(digits);
+
+                // `round` takes halves away from zero, `roundeven` to the even neighbour (e.g. 2.5 -> 2)
+                let scaled = number * factor;
+
+                // More decimals than a float keeps leave the number as it is, rounding left of its
+                // first digit gives 0
+                if !scaled.is_finite()
+                {
+                    return Ok(LiteralValue::Float(number));
+                }
+
+                if factor == 0.0
+                {
+                    return Ok(LiteralValue::Float(0.0));
+                }
+
+                let rounded = if self.0 == "round" { scaled.round() } else { scaled.round_ties_even() };
+
+                return Ok(LiteralValue::Float(rounded / factor));
+            },
+            "roundsig" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let number = self.next_number(context)?;
+                let figures = self.next_number(context)?.trunc();
+
+                if figures < 1.0
+                {
+                    panic!("Function `roundsig` keeps at least 1 significant figure, got `{}`", figures);
+                }
+
+                if number == 0.0
+                {
+                    return Ok(LiteralValue::Float(0.0));
+                }
+
+                // The decimal places that keep `figures` digits, negative ones round to the left of the point
+                let digits = figures as i32 - 1 - number.abs().log10().floor() as i32;
+                let factor = 10f64.powi(digits);
+
+                return Ok(LiteralValue::Float((number * factor).round() / factor));
+            },
+            "mround" | "floorm" | "ceilm" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let number = self.next_number(context)?;
+                let multiple = self.next_number(context)?.abs();
+
+                if multiple == 0.0
+                {
+                    panic!("Function `{}` can't round to a multiple of 0", self.0);
+                }
+
+                // Binary fractions make e.g. `0.3 / 0.1` fall just below 3
+                let quotient = number / multiple;
+                let quotient = if (quotient - quotient.round()).abs() < 1e-9 { quotient.round() } else { quotient };
+
+                let rounded = match self.0.as_str()
+                {
+                    "mround" => quotient.round(),
+                    "floorm" => quotient.floor(),
+                    _        => quotient.ceil(),
+                };
+
+                return Ok(LiteralValue::Float(rounded * multiple));
+            },
+            "hypot" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let a = self.next_number(context)?;
+                let b = self.next_number(context)?;
+
+                return Ok(LiteralValue::Float(a.hypot(b)));
+            },
+            "fact" =>
+            {
+                self.expect_args(1, Some(1));
+
+                let n = self.next_count(context)?;
+
+                // Past `fact(170)` the product overflows to infinity, checked before looping so a huge `n`
+                // doesn't keep multiplying
+                if n > 170.0
+                {
+                    panic!("Function `fact` got `{}`, its result is too large", n);
+                }
+
+                let product = (2..=n as u64).fold(1.0, |product, i| product * i as f64);
+
+                return Ok(LiteralValue::Float(product));
+            },
+            "combin" | "permut" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let n = self.next_count(context)?;
+                let k = self.next_count(context)?;
+
+                if k > n
+                {
+                    panic!("Function `{}` got `{}` items to choose out of `{}`", self.0, k, n);
+                }
+
+                // Multiplying and dividing in turns keeps the intermediate values small, `combin`
+                // takes the smaller of `k` and `n - k` since both choose the same number of ways
+                let smaller = k.min(n - k);
+                let steps = if self.0 == "permut" { k } else { smaller };
+                let mut result = 1.0;
+
+                for i in 0..steps as u64
+                {
+                    result = if self.0 == "permut"
+                    {
+                        result * (n - i as f64)
+                    }
+                    else
+                    {
+                        result * (n - smaller + 1.0 + i as f64) / (1.0 + i as f64)
+                    };
+
+                    // Once infinite it stays so, a huge `n` would otherwise keep looping
+                    if result.is_infinite()
+                    {
+                        break;
+                    }
+                }
+
+                let result = result.round();
+
+                if result.is_infinite()
+                {
+                    panic!("Function `{}` got `{}` and `{}`, its result is too large", self.0, n, k);
+                }
+
+                return Ok(LiteralValue::Float(result));
+            },
+            "pi" =>
+            {
+                self.expect_args(0, Some(0));
+
+                return Ok(LiteralValue::Float(std::f64::consts::PI));
+            },
+            "degrees" | "radians" =>
+            {
+                self.expect_args(1, Some(1));
+
+                let angle = self.next_number(context)?;
+
+                return Ok(LiteralValue::Float(if self.0 == "degrees" { angle.to_degrees() } else { angle.to_radians() }));
+            },
+            "asin" | "acos" | "atan" =>
+            {
+                self.expect_args(1, Some(1));
+
+                let x = self.next_number(context)?;
+
+                // A NaN would silently spread to every formula using the result
+                if self.0 != "atan" && !(-1.0..=1.0).contains(&x)
+                {
+                    panic!("Function `{}` got `{}`, expected a number from -1 to 1", self.0, x);
+                }
+
+                return Ok(LiteralValue::Float(match self.0.as_str()
+                {
+                    "asin" => x.asin(),
+                    "acos" => x.acos(),
+                    _      => x.atan(),
+                }));
+            },
+            "atan2" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let y = self.next_number(context)?;
+                let x = self.next_number(context)?;
+
+                // The angle of the point `(x, y)`, from -pi to pi, so the quadrant isn't lost like with `atan(y/x)`
+                return Ok(LiteralValue::Float(y.atan2(x)));
+            },
+            "norm" =>
+            {
+                self.expect_args(1, None);
+
+                // The Euclidean norm of every component, ranges give one component per number
+                let numbers = self.numbers(context)?;
+
+                return Ok(LiteralValue::Float(numbers.into_iter().fold(0.0, f64::hypot)));
+            },
+            "n" =>
+            {
+                self.expect_args(1, Some(1));
+
+                // Blank cells already evaluate to 0, texts give 0 too instead of failing
+                return match self.1.remove(0).evaluate(context)?
+                {
+                    LiteralValue::Float(f) => Ok(LiteralValue::Float(f)),
+                    LiteralValue::Int(i) => Ok(LiteralValue::Int(i)),
+                    _ => Ok(LiteralValue::Float(0.0)),
+                };
+            },
+            "first" | "last" =>
+            {
+                self.expect_args(1, None);
+
+                // `last` looks from the end, both stop at the first non-blank value so the
+                // arguments after it aren't evaluated
+                if self.0 == "last"
+                {
+                    self.1.reverse();
+                }
+
+                while !self.1.is_empty()
+                {
+                    let mut param = self.1.remove(0);
+
+                    // A blank cell would evaluate to 0
+                    if param.cell_index().is_some_and(|index| CellRef::is_blank(&index, context))
+                    {
+                        continue;
+                    }
+
+                    let values = match param.evaluate_range(context)?
+                    {
+                        Some(values) => values,
+                        None => vec![param.evaluate(context)?],
+                    };
+
+                    let mut values = values.into_iter()
+                        .filter(|value| !matches!(value, LiteralValue::Text(text) if text.trim().is_empty()));
+
+                    if let Some(value) = if self.0 == "last" { values.next_back() } else { values.next() }
+                    {
+                        return Ok(value);
+                    }
+                }
+
+                panic!("Function `{}` only got blank arguments", self.0);
+            },
+            "copysign" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let magnitude = self.next_number(context)?;
+                let sign = self.next_number(context)?;
+
+                // The sign of `-0` counts, so `copysign(1, -0)` is `-1`
+                return Ok(LiteralValue::Float(magnitude.copysign(sign)));
+            },
+            "nearbyint" =>
+            {
+                self.expect_args(1, Some(1));
+
+                // Halves go to the even neighbour, like `roundeven` (e.g. 2.5 -> 2, -3.5 -> -4)
+                return Ok(LiteralValue::Float(self.next_number(context)?.round_ties_even()));
+            },
+            "countblank" =>
+            {
+                let mut count = 0;
+
+                while !self.1.is_empty()
+                {
+                    let mut param = self.1.remove(0);
+
+                    match param.range_cells().or(param.cell_index().map(|cell_index| vec![cell_index]))
+                    {
+                        Some(cells) =>
+                        {
+                            for cell_index in cells
+                            {
+                                // A formula giving an empty text counts as blank too
+                                if CellRef::is_blank(&cell_index, context)
+                                    || matches!(CellRef::resolve(cell_index, context)?, LiteralValue::Text(text) if text.is_empty())
+                                {
+                                    count += 1;
+                                }
+                            }
+                        },
+                        None =>
+                        {
+                            if matches!(param.evaluate(context)?, LiteralValue::Text(text) if text.is_empty())
+                            {
+                                count += 1;
+                            }
+                        }
+                    }
+                }
+
+                return Ok(LiteralValue::Float(count as f64));
+            },
+            "date" =>
+            {
+                self.expect_args(3, Some(3));
+
+                let year = self.next_number(context)?.trunc() as i64;
+                let month = self.next_number(context)?.trunc() as i64;
+                let day = self.next_number(context)?.trunc() as i64;
+
+                return Ok(LiteralValue::Float(Date::serial(year, month, day) as f64));
+            },
+            "today" =>
+            {
+                self.expect_args(0, Some(0));
+
+                return Ok(LiteralValue::Float(Date::today() as f64));
+            },
+            "year" | "month" | "weekday" =>
+            {
+                self.expect_args(1, Some(1));
+
+                let serial = self.next_number(context)?;
+                let date = Date::from_serial(serial);
+
+                return Ok(LiteralValue::Float(match self.0.as_str()
+                {
+                    "year"  => date.year,
+                    "month" => date.month,
+                    // 1 for Sunday through 7 for Saturday, the serial 0 is a Saturday
+                    _ => (serial.floor() as i64 + 6).rem_euclid(7) + 1,
+                } as f64));
+            },
+            "value" =>
+            {
+                self.expect_args(1, Some(1));
+
+                let text = self.next_text(context)?;
+
+                match Cell::literal(text.trim())
+                {
+                    LiteralValue::Float(f) => return Ok(LiteralValue::Float(f)),
+                    LiteralValue::Int(i) => return Ok(LiteralValue::Int(i)),
+                    _ => panic!("Function `value` can't convert `{}` to a number", text)
+                }
+            },
+            "text" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let value = self.next_number(context)?;
+                let format = self.next_text(context)?;
+
+                let number_format = NumberFormat::parse(&format)
+                    .unwrap_or_else(|| panic!("Function `text` doesn't support the format `{}`", format));
+
+                return Ok(LiteralValue::Text(number_format.format(value)));
+            },
+            "iseven" | "isodd" =>
+            {
+                self.expect_args(1, Some(1));
+
+                // Like spreadsheets, fractions are truncated (`iseven(2.7)` is true)
+                let is_even = self.next_number(context)?.trunc() % 2.0 == 0.0;
+
+                return Ok(LiteralValue::Float(if is_even == (self.0 == "iseven") { 1.0 } else { 0.0 }));
+            },
+            "iserror" | "isna" =>
+            {
+                self.expect_args(1, Some(1));
+
+                let mut param = self.1.remove(0);
+                let depth = context.visiting.len();
+
+                // The failure is the result
+                let evaluated = catch_panic(|| param.evaluate(context));
+
+                // A panic leaves the cells it was evaluating on the visiting list
+                context.visiting.truncate(depth);
+
+                // `isna` only takes a missing value as a failure, `iserror` takes any failure and the
+                // numbers that aren't finite (e.g. a division by zero)
+                let failed = match evaluated
+                {
+                    // Not a failure of the argument but of the sheet
+                    Ok(Err(error @ EvalError::Cycle(_))) => return Err(error),
+                    // Whether the `#REF!` is cached yet or not
+                    Ok(Err(EvalError::UnknownCell(_) | EvalError::NoSheet(_) | EvalError::InvalidReference(_))) => true,
+                    Ok(Err(_)) | Err(_) => self.0 == "iserror",
+                    Ok(Ok(LiteralValue::Float(f))) => self.0 == "iserror" && !f.is_finite(),
+                    Ok(Ok(_)) => false,
+                };
+
+                return Ok(LiteralValue::Float(if failed { 1.0 } else { 0.0 }));
+            },
+            "isref" =>
+            {
+                self.expect_args(1, Some(1));
+
+                // Only the written argument counts, it isn't evaluated
+                let param = &self.1[0];
+                let is_ref = param.cell_index().is_some() || param.range_cells().is_some();
+
+                return Ok(LiteralValue::Float(if is_ref { 1.0 } else { 0.0 }));
+            },
+            "coalesce" =>
+            {
+                self.expect_args(2, Some(2));
+
+                let mut param = self.1.remove(0);
+
+                // Unlike `iserror`, only a blank argument takes the default (e.g. an optional input),
+                // a missing cell is blank too and a formula giving an empty text counts as blank
+                let value = match param.cell_index()
+                {
+                    Some(cell_index) if CellRef::is_blank(&cell_index, context) => None,
+                    Some(cell_index) => Some(CellRef::resolve(cell_index, context)?),
+                    None => Some(param.evaluate(context)?),
+                };
+
+                return match value
+                {
+                    Some(LiteralValue::Text(text)) if text.is_empty() => self.1.remove(0).evaluate(context),
+                    Some(value) => Ok(value),
+                    None => self.1.remove(0).evaluate(context),
+                };
+            },
+            "sequence" | "randarray" | "runningsum" =>
+            {
+                panic!("Function `{}` can only be used as a whole formula or as a function argument", self.0);
+            },
+            "vlookup" =>
+            {
+                todo!();
+            },
+            "concatenate" =>
+            {
+                let texts = self.texts(context, false)?;
+
+                return Ok(LiteralValue::Text(texts.concat()));
+            },
+            "textjoin" =>
+            {
+                self.expect_args(3, None);
+
+                let delimiter = self.next_text(context)?;
+                let ignore_empty = self.next_number(context)? != 0.0;
+
+                let mut texts = self.texts(context, !ignore_empty)?;
+
+                if ignore_empty
+                {
+                    texts.retain(|text| !text.is_empty());
+                }
+
+                return Ok(LiteralValue::Text(texts.join(&delimiter)));
+            },
+            "joinformat" =>
+            {
+                self.expect_args(3, None);
+
+                let delimiter = self.next_text(context)?;
+                let format = self.next_text(context)?;
+
+                let number_format = NumberFormat::parse(&format)
+                    .unwrap_or_else(|| panic!("Function `joinformat` doesn't support the format `{}`", format));
+
+                // `joinformat(delimiter, format, values...)`, numbers are written like `text(value, format)`,
+                // texts are joined as they are and blank cells of ranges are left out
+                let mut texts = Vec::<String>::new();
+
+                while !self.1.is_empty()
+                {
+                    let mut param = self.1.remove(0);
+
+                    let values = match param.evaluate_range(context)?
+                    {
+                        Some(values) => values,
+                        None => vec![param.evaluate(context)?],
+                    };
+
+                    texts.extend(values.into_iter().map(|value|
+                        match value
+                        {
+                            LiteralValue::Float(f) => number_format.format(f),
+                            LiteralValue::Int(i) => number_format.format(i as f64),
+                            LiteralValue::Text(text) => text,
+                            _ => panic!("Expected numbers or text as `joinformat` params")
+                        }));
+                }
+
+                return Ok(LiteralValue::Text(texts.join(&delimiter)));
+            },
+            _ => todo!("Not all FUNCTIONS are implemented")
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Literal(Token);
+
+impl Literal
+{
+    pub fn new(literal: Token) -> Self
+    {
+        Literal(literal)
+    }
+
+    fn from_value(value: LiteralValue) -> Self
+    {
+        match &value
+        {
+            LiteralValue::Float(f) => Literal(Token::new(TokenType::Number, f.to_string(), Some(value))),
+            LiteralValue::Int(i) => Literal(Token::new(TokenType::Number, i.to_string(), Some(value))),
+            LiteralValue::Text(text) => Literal(Token::new(TokenType::Text, format!("{:?}", text), Some(value))),
+            _ => unreachable!()
+        }
+    }
+}
+
+impl Expression for Literal
+{
+    fn collect_refs(&self, _out: &mut Vec<CellIndex>, _ranges: bool) -> ()
+    {
+    }
+
+    // Booleans are numbers, like when they're evaluated
+    fn to_json(&self) -> String
+    {
+        match self.0.literal.as_ref()
+        {
+            Some(LiteralValue::Float(f)) => format!("{{\"type\": \"number\", \"value\": {}}}", f),
+            Some(LiteralValue::Int(i)) => format!("{{\"type\": \"number\", \"value\": {}}}", i),
+            Some(LiteralValue::Text(text)) => format!("{{\"type\": \"text\", \"value\": {}}}", json_string(text)),
+            _ => unreachable!()
+        }
+    }
+
+    fn evaluate(&mut self, _context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        match self.0.get_type()
+        {
+            TokenType::Number | TokenType::Text | TokenType::Boolean => Ok(self.0.literal.take().unwrap()),
+            _ => todo!()
+        }
+    }
+
+    fn try_eval_const(&self) -> Option<LiteralValue>
+    {
+        self.0.literal.clone()
+    }
+}
+
+#[derive(Debug)]
+pub struct CellRef(Token);
+
+impl CellRef
+{
+    pub fn new(token: Token) -> Self
+    {
+        return CellRef(token);
+    }
+
+    // Anything but letters (e.g. an empty name or digits) would underflow, and too many letters overflow
+    pub fn text_to_number(column_name: String) -> usize
+    {
+        if column_name.is_empty() || !column_name.chars().all(|c| c.is_ascii_alphabetic())
+        {
+            panic!("Invalid cell reference letters: `{}`, expected at least one letter (e.g. `A` or `AB`)", column_name);
+        }
+
+        let column_name = column_name.to_uppercase();
+
+        let mut sum: usize = 0;
+        let ac = 'A' as usize;
+    
+        for i in 0..column_name.len()
+        {
+            sum = sum.checked_mul(26)
+                .and_then(|sum| sum.checked_add((column_name.chars().nth(i).unwrap() as usize) - ac + 1))
+                .unwrap_or_else(|| panic!("Cell reference letters `{}` are out of range", column_name));
+        }
+    
+        return sum - 1;
+    }
+
+    // Inverse of `text_to_number`: 0 -> A, 25 -> Z, 26 -> AA, 701 -> ZZ
+    pub fn number_to_text(column: usize) -> String
+    {
+        let mut n = column + 1;
+        let mut s = String::new();
+
+        while n > 0
+        {
+            let remainder = (n - 1) % 26;
+            s.insert(0, (b'A' + remainder as u8) as char);
+            n = (n - 1) / 26;
+        }
+
+        s
+    }
+
+    // Adjusts the references of a formula (without its `=`) written in `from` so that it can be
+    // written in `to`, references anchored with `$` (e.g. `$A1`, `A$1`) keep their letters or digits
+    pub fn copy_formula(formula: &str, from: &CellIndex, to: &CellIndex) -> String
+    {
+        let ((from_row, from_column), (to_row, to_column)) = (from.get(), to.get());
+
+        let shift = |index: usize, source: usize, target: usize, reference: &str| -> usize
+        {
+            (index + target).checked_sub(source).unwrap_or_else(||
+                panic!("Copying `{}` from `{}` to `{}` moves `{}` out of the sheet", formula, from, to, reference))
+        };
+
+        let mut copied = String::new();
+        let mut rest = formula;
+
+        for token in Tokenizer::new(formula.to_string()).in_cell(from, &[]).get_tokens()
+        {
+            let lexeme = token.get_lexeme();
+            let start = rest.find(lexeme.as_str()).unwrap();
+
+            copied.push_str(&rest[..start]);
+            rest = &rest[start + lexeme.len()..];
+
+            match &token.literal
+            {
+                Some(LiteralValue::CellRef(index)) =>
+                {
+                    // The sheet prefix (e.g. `Sheet2!`) is kept as it is
+                    let prefix = lexeme.rfind('!').map_or("", |end| &lexeme[..=end]);
+                    let label = &lexeme[prefix.len()..];
+
+                    // Relative references (e.g. `RC[-1]`) already follow the formula
+                    if CellIndex::from_label(label).is_none()
+                    {
+                        copied.push_str(lexeme);
+                        continue;
+                    }
+
+                    let (row, column) = index.get();
+                    let row_anchored = label.starts_with('$');
+                    let column_anchored = label[1..].contains('$');
+
+                    let row = if row_anchored { row } else { shift(row, from_row, to_row, lexeme) };
+                    let column = if column_anchored { column } else { shift(column, from_column, to_column, lexeme) };
+
+                    copied.push_str(&format!("{}{}{}{}{}", prefix,
+                        if row_anchored { "$" } else { "" }, CellRef::number_to_text(row),
+                        if column_anchored { "$" } else { "" }, column));
+                },
+                _ => copied.push_str(lexeme),
+            }
+        }
+
+        copied.push_str(rest);
+
+        copied
+    }
+
+    // An empty value or a cell that isn't defined at all, formulas are never blank
+    fn is_blank(cell_index: &CellIndex, context: &Context) -> bool
+    {
+        match context.value_cells.get(cell_index)
+        {
+            Some(Cell::Value(value)) => value.trim().is_empty(),
+            _ => !context.expr_cells.contains_key(cell_index),
+        }
+    }
+
+    // Tokenized like when it's evaluated, so references to other sheets and registered functions are known
+    fn calls_random(formula: &str, cell_index: &CellIndex, context: &Context) -> bool
+    {
+        Tokenizer::new(formula.to_string()).in_cell(cell_index, context.sheet_names).with_functions(context.functions.keys())
+            .get_tokens().iter().any(|token|
+            *token.get_type() == TokenType::Function && ["random", "randbetween", "randarray"].contains(&token.get_lexeme().to_lowercase().as_str()))
+    }
+
+    fn resolve(cell_index: CellIndex, context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        let recalc = context.force_recalc
+            && matches!(context.expr_cells.get(&cell_index), Some(Cell::Expression(expr)) if CellRef::calls_random(expr, &cell_index, context));
+
+        let cell = match if recalc { None } else { context.value_cells.get(&cell_index) }.or(context.expr_cells.get(&cell_index))
+        {
+            Some(cell) => cell,
+            None => return Err(EvalError::UnknownCell(cell_index)),
+        };
+
+        match cell
+        {
+            Cell::Expression(expr) =>
+            {
+                // Cells visited before the cycle was entered aren't part of it
+                if let Some(position) = context.visiting.iter().position(|x| *x == cell_index)
+                {
+                    return Err(EvalError::Cycle(context.visiting[position..].to_vec()));
+                }
+
+                let depth = context.visiting.len();
+                context.visiting.push(cell_index.clone());
+
+                let mut expression = Parser::in_cell(expr, &cell_index, context.sheet_names, context.functions.keys()).parse();
+
+                // A new value for this reference only, caching it would change the value of the cell itself
+                // (and of the references before this one). Like in a cell, a spilling formula gives its first value
+                let evaluated = if !recalc
+                {
+                    Sheet::evaluate_formula(&cell_index, expression, context)
+                }
+                else if expression.spills()
+                {
+                    expression.evaluate_range(context).map(|values| values.unwrap().remove(0))
+                }
+                else
+                {
+                    expression.evaluate(context)
+                };
+
+                // Also on failures, so that a formula going on after a failed reference (e.g. in `iserror`)
+                // doesn't take the cells it evaluates next for a cycle
+                context.visiting.truncate(depth);
+
+                return evaluated;
+            },
+            Cell::Value(value) =>
+            {
+                if value.is_empty()
+                {
+                    return Ok(LiteralValue::Float(0.0));
+                }
+
+                // Like in spreadsheets, the formulas referring to a `#REF!` cell are `#REF!` too
+                if value == REF_ERROR
+                {
+                    return Err(EvalError::InvalidReference(format!("Referring to {}, which is {}", cell_index, REF_ERROR)));
+                }
+
+                return Ok(Cell::literal(value));
+            }
+        }
+    }
+}
+
+impl Expression for CellRef
+{
+    fn collect_refs(&self, out: &mut Vec<CellIndex>, _ranges: bool) -> ()
+    {
+        out.extend(self.cell_index());
+    }
+
+    // `ref` is the reference as written (e.g. `$A$1` or `RC[-1]`), `cell` the label it resolves to
+    fn to_json(&self) -> String
+    {
+        format!("{{\"type\": \"cell\", \"ref\": {}, \"cell\": {}}}",
+            json_string(self.0.get_lexeme()), json_string(&self.cell_index().unwrap().to_string()))
+    }
+
+    fn evaluate(&mut self, context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        CellRef::resolve(self.cell_index().unwrap(), context)
+    }
+
+    fn cell_index(&self) -> Option<CellIndex>
+    {
+        if let Some(LiteralValue::CellRef(cell_index)) = self.0.literal.as_ref()
+        {
+            return Some(cell_index.clone());
+        }
+
+        None
+    }
+}
+
+#[derive(Debug)]
+struct Range(Token, Token);
+
+impl Range
+{
+    pub fn new(from: Token, to: Token) -> Self
+    {
+        Range(from, to)
+    }
+
+    fn bounds(&self) -> (CellIndex, CellIndex)
+    {
+        match (self.0.literal.as_ref(), self.1.literal.as_ref())
+        {
+            (Some(LiteralValue::CellRef(from)), Some(LiteralValue::CellRef(to))) =>
+            {
+                // Only the first cell needs a sheet prefix (e.g. `Sheet2!A0:B3`)
+                if self.1.get_lexeme().contains('!') && to.sheet() != from.sheet()
+                {
+                    panic!("Range `{}:{}` spans two sheets", self.0.get_lexeme(), self.1.get_lexeme());
+                }
+
+                let (from_row, from_column) = from.get();
+                let (to_row, to_column) = to.get();
+
+                (CellIndex::new(from_row.min(to_row), from_column.min(to_column)).with_sheet(from.sheet()),
+                    CellIndex::new(from_row.max(to_row), from_column.max(to_column)).with_sheet(from.sheet()))
+            },
+            _ => unreachable!()
+        }
+    }
+}
+
+impl Expression for Range
+{
+    fn collect_refs(&self, out: &mut Vec<CellIndex>, ranges: bool) -> ()
+    {
+        if ranges
+        {
+            out.extend(self.range_cells().unwrap());
+        }
+    }
+
+    fn to_json(&self) -> String
+    {
+        format!("{{\"type\": \"range\", \"from\": {}, \"to\": {}}}", json_string(self.0.get_lexeme()), json_string(self.1.get_lexeme()))
+    }
+
+    fn evaluate(&mut self, _context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        panic!("Range `{}:{}` can only be used as a function argument", self.0.get_lexeme(), self.1.get_lexeme());
+    }
+
+    fn evaluate_range(&mut self, context: &mut Context) -> Result<Option<Vec<LiteralValue>>, EvalError>
+    {
+        let mut values = Vec::<LiteralValue>::new();
+
+        for cell_index in self.range_cells().unwrap()
+        {
+            if !CellRef::is_blank(&cell_index, context)
+            {
+                values.push(CellRef::resolve(cell_index, context)?);
+            }
+        }
+
+        Ok(Some(values))
+    }
+
+    fn range_cells(&self) -> Option<Vec<CellIndex>>
+    {
+        let (from, to) = self.bounds();
+        let ((from_row, from_column), (to_row, to_column)) = (from.get(), to.get());
+        let sheet = from.sheet();
+
+        Some((from_row..=to_row)
+            .flat_map(|row| (from_column..=to_column).map(move |column| CellIndex::new(row, column).with_sheet(sheet)))
+            .collect())
+    }
+}
+
+#[derive(Debug)]
+struct Group(Box<dyn Expression>);
+
+impl Group
+{
+    pub fn new(literal: Box<dyn Expression>) -> Self
+    {
+        Group(literal)
+    }
+}
+
+impl Expression for Group
+{
+    fn collect_refs(&self, out: &mut Vec<CellIndex>, ranges: bool) -> ()
+    {
+        self.0.collect_refs(out, ranges);
+    }
+
+    fn to_json(&self) -> String
+    {
+        format!("{{\"type\": \"group\", \"expression\": {}}}", self.0.to_json())
+    }
+
+    fn evaluate(&mut self, context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        self.0.evaluate(context)
+    }
+
+    fn try_eval_const(&self) -> Option<LiteralValue>
+    {
+        self.0.try_eval_const()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Cell
+{
+    Value(String),
+    Expression(String),
+}
+
+impl Cell
+{
+    // The cells a formula written in `index` refers to, as written (a cell referred to twice comes twice),
+    // a value refers to none. The sheet names aren't known, so prefixed references stay in the sheet of `index`.
+    // `functions` are the names of the registered functions the formula may call
+    pub fn references(&self, index: &CellIndex, functions: &[String]) -> Vec<CellIndex>
+    {
+        let mut references = Vec::<CellIndex>::new();
+
+        if let Cell::Expression(expr) = self
+        {
+            // Only the references are needed, folding would evaluate the constant calls
+            Parser::in_cell(expr, index, &[], functions).without_folding().parse().collect_refs(&mut references, true);
+        }
+
+        references
+    }
+
+    pub fn is_formula(&self) -> bool
+    {
+        matches!(self, Cell::Expression(_))
+    }
+
+    pub fn as_value(&self) -> Option<&str>
+    {
+        match self
+        {
+            Cell::Value(value) => Some(value),
+            Cell::Expression(_) => None,
+        }
+    }
+
+    // The formula without its leading `=`
+    pub fn as_formula(&self) -> Option<&str>
+    {
+        match self
+        {
+            Cell::Value(_) => None,
+            Cell::Expression(expr) => Some(expr),
+        }
+    }
+
+    // Integers are read exactly, like integer literals
+    fn literal(value: &str) -> LiteralValue
+    {
+        if let Ok(i) = value.parse::<i64>()
+        {
+            return LiteralValue::Int(i);
+        }
+
+        match value.parse::<f64>()
+        {
+            Ok(f) => LiteralValue::Float(f),
+            Err(_) => LiteralValue::Text(value.to_string()),
+        }
+    }
+
+    // The value cell of an evaluated number or text, numbers are written like in an evaluated sheet
+    pub fn from_literal(value: &LiteralValue) -> Self
+    {
+        match value
+        {
+            LiteralValue::Float(f) => Cell::Value(Cell::number(*f)),
+            LiteralValue::Int(i) => Cell::Value(i.to_string()),
+            LiteralValue::Text(text) => Cell::Value(text.clone()),
+            _ => unreachable!()
+        }
+    }
+
+    // Like spreadsheets, keeps at most 15 significant digits so that `0.1+0.2` reads `0.3`
+    fn number(f: f64) -> String
+    {
+        if f == 0.0 || !f.is_finite()
+        {
+            return f.to_string();
+        }
+
+        format!("{:.14e}", f).parse::<f64>().unwrap().to_string()
+    }
+
+    fn display(&self) -> String
+    {
+        match self
+        {
+            Cell::Value(val)       => val.clone(),
+            Cell::Expression(expr) => format!("={}", expr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width
+{
+    Fixed(usize),
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment
+{
+    Left,
+    Right,
+    Center,
+    // Numbers to the right, text to the left
+    Auto,
+}
+
+impl Alignment
+{
+    fn pad(&self, content: &str, width: usize) -> String
+    {
+        match self
+        {
+            Alignment::Left   => format!("{: <width$}", content, width = width),
+            Alignment::Right  => format!("{: >width$}", content, width = width),
+            Alignment::Center => format!("{: ^width$}", content, width = width),
+            Alignment::Auto   => Alignment::Left.pad(content, width),
+        }
+    }
+
+    // `Auto` picks the side from the value before it's formatted (e.g. `1,000` is still a number)
+    fn for_value(&self, value: &str) -> Alignment
+    {
+        match self
+        {
+            Alignment::Auto if value.parse::<f64>().is_ok() => Alignment::Right,
+            Alignment::Auto => Alignment::Left,
+            alignment => *alignment,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FormatOptions
+{
+    pub width              : Width,
+    pub alignment          : Alignment,
+    pub emit_formulas      : bool,
+    // Formula cells show their formula after their value (e.g. `3 (=A0+A1)`), unless `emit_formulas`
+    pub with_formulas      : bool,
+    pub null_value         : Option<String>,
+    pub color              : bool,
+    // Groups the digits of numbers by three (e.g. `1,000,000`), only for reading, so not with `emit_formulas`
+    pub thousands_separator: Option<char>,
+    // Leaves out the rows whose cells are all blank (e.g. spacer rows), the other rows keep their order
+    pub trim_blank_rows    : bool,
+    // Writes numbers rounded to this many decimals, only for reading, so not with `emit_formulas`
+    pub round_output       : Option<usize>,
+    // Written as the `=== Name ===` line of the unnamed sheet, the named sheets have their own
+    pub sheet_name         : Option<String>,
+    // Writes only these columns in this order, a row missing one of them gets a blank cell there
+    pub columns            : Option<Vec<usize>>,
+    // Numbers of these columns are written with their format instead of `round_output` and `thousands_separator`
+    pub column_formats     : HashMap<usize, NumberFormat>,
+}
+
+impl Default for FormatOptions
+{
+    fn default() -> Self
+    {
+        FormatOptions
+        {
+            width              : Width::Fixed(10),
+            alignment          : Alignment::Left,
+            emit_formulas      : false,
+            with_formulas      : false,
+            null_value         : None,
+            color              : false,
+            thousands_separator: None,
+            trim_blank_rows    : false,
+            round_output       : None,
+            sheet_name         : None,
+            columns            : None,
+            column_formats     : HashMap::new(),
+        }
+    }
+}
+
+// How an input is split into cells, see `Sheet::with_options`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions
+{
+    pub header_rows: usize,
+    // Tabs separate columns like `|` (`--tab-width`, which takes no value)
+    pub tab_width  : bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dimensions
+{
+    pub rows    : usize,
+    pub columns : usize,
+    pub values  : usize,
+    pub formulas: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintWarning
+{
+    pub cell     : CellIndex,
+    pub reference: CellIndex,
+    pub undefined: bool,
+}
+
+impl fmt::Display for LintWarning
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}: refers to {} cell {}", self.cell, if self.undefined { "an undefined" } else { "a blank" }, self.reference)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind
+{
+    Load,
+    Parse,
+    Cycle,
+    Eval,
+}
+
+impl fmt::Display for DiagnosticKind
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            DiagnosticKind::Load  => write!(f, "load"),
+            DiagnosticKind::Parse => write!(f, "parse"),
+            DiagnosticKind::Cycle => write!(f, "cycle"),
+            DiagnosticKind::Eval  => write!(f, "eval"),
+        }
+    }
+}
+
+// `cell` is `None` when the input couldn't be loaded at all, `sheet` is the name of its sheet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic
+{
+    pub cell   : Option<CellIndex>,
+    pub sheet  : String,
+    pub kind   : DiagnosticKind,
+    pub message: String,
+}
+
+// A cell whose value differs between two sheets, `old` or `new` is `None` for a cell only one of
+// them has. `sheet` is the name of its sheet
+#[derive(Debug, Clone)]
+pub struct CellDiff
+{
+    pub cell : CellIndex,
+    pub sheet: String,
+    pub old  : Option<LiteralValue>,
+    pub new  : Option<LiteralValue>,
+}
+
+// Prefixed by the cell when there's one (e.g. `Sheet2!A1: Cycle detected: ...`)
+impl fmt::Display for Diagnostic
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match (&self.cell, self.sheet.as_str())
+        {
+            (None, _) => write!(f, "{}", self.message),
+            (Some(cell), "") => write!(f, "{}: {}", cell, self.message),
+            (Some(cell), sheet) => write!(f, "{}!{}: {}", sheet, cell, self.message),
+        }
+    }
+}
+
+// Quotes and escapes `text` as a JSON string
+pub fn json_string(text: &str) -> String
+{
+    let mut json = String::from('"');
+
+    for c in text.chars()
+    {
+        match c
+        {
+            '"'  => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+
+    json.push('"');
+    json
+}
+
+// The parse tree of a formula cell, see `Sheet::syntax_trees`. `sheet` is the name of its sheet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxTree
+{
+    pub cell : CellIndex,
+    pub sheet: String,
+    // The tree as JSON (see `Expression::to_json`), or why the formula couldn't be parsed
+    pub json : Result<String, String>,
+}
+
+thread_local!
+{
+    // How many `catch_panic` calls this thread is in, their panics aren't printed
+    static CATCHING: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+// Only cycles are returned as errors, every other failure panics, so diagnostics catch the panics.
+// A caught panic is the result, so it isn't printed by the panic hook
+pub fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String>
+{
+    static SILENCING_HOOK: Once = Once::new();
+
+    // Swapping the hook on every call would race with other threads, so it's wrapped once and
+    // still prints the panics nothing catches
+    SILENCING_HOOK.call_once(||
+    {
+        let previous = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info|
+        {
+            if CATCHING.with(|depth| depth.get()) == 0
+            {
+                previous(info);
+            }
+        }));
+    });
+
+    CATCHING.with(|depth| depth.set(depth.get() + 1));
+    let caught = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    CATCHING.with(|depth| depth.set(depth.get() - 1));
+
+    caught.map_err(|payload|
+        payload.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("Unknown error")))
+}
+
+#[derive(Default, Clone)]
+pub struct Sheet
+{
+    expr_cells   : Table,
+    value_cells  : Table,
+    sheet_names  : Vec<String>,
+    // The value cells as loaded, evaluating adds the values of the formulas and the cells they spill to
+    loaded_values: usize,
+    // The names of the functions registered with the `Evaluator` loading it, so its formulas can
+    // also be parsed without evaluating them (e.g. by `validate` or `lint`)
+    functions    : Vec<String>,
+}
+
+impl Sheet
+{
+    pub fn new(file_content: &str) -> Self
+    {
+        Sheet::with_options(file_content, &LoadOptions::default())
+    }
+
+    // The first `header_rows` rows are only values, they keep their place so references to
+    // the rows below are the same with or without them
+    pub fn with_header_rows(file_content: &str, header_rows: usize) -> Self
+    {
+        Sheet::with_options(file_content, &LoadOptions { header_rows, ..LoadOptions::default() })
+    }
+
+    // `tab_width` makes tabs separate columns like `|` (e.g. for pasted tab-aligned data), so a
+    // formula can only have tabs within parentheses or text
+    pub fn with_options(file_content: &str, options: &LoadOptions) -> Self
+    {
+        let mut sheet = Sheet::default();
+        sheet.load(file_content, options);
+
+        sheet
+    }
+
+    // Expects an empty sheet, so that its tables can be reused.
+    // A line like `=== Name ===` starts a new sheet, its rows are counted from 0 again.
+    // The lines before the first one are an unnamed sheet
+    fn load(&mut self, file_content: &str, options: &LoadOptions) -> ()
+    {
+        self.sheet_names.clear();
+        self.sheet_names.push(String::new());
+        self.loaded_values = 0;
+
+        // An empty input would otherwise give a phantom blank cell
+        if file_content.trim().is_empty()
+        {
+            return;
+        }
+
+        let lines = preprocessing::split_lines(file_content);
+        let mut sheet = 0;
+        let mut row = 0;
+
+        for line in lines
+        {
+            if let Some(name) = Sheet::sheet_header(line)
+            {
+                if self.sheet_names.iter().any(|sheet_name| sheet_name.eq_ignore_ascii_case(name))
+                {
+                    panic!("Sheet `{}` is declared twice", name);
+                }
+
+                self.sheet_names.push(name.to_string());
+                sheet += 1;
+                row = 0;
+
+                continue;
+            }
+
+            let columns = Sheet::split_columns(line, options.tab_width);
+
+            for (column, cell) in columns.iter().enumerate()
+            {
+                // Only the first character makes a formula, so `a=b` is a value
+                if cell.starts_with('=') && row >= options.header_rows
+                {
+                    let mut content = cell.to_string();
+                    content.remove(0); // Delete '='
+
+                    // `==1` reads like a comparison missing its left operand rather than a formula
+                    if content.starts_with('=')
+                    {
+                        panic!("Cell `{}` starts with `==`, a formula starts with a single `=` (e.g. `=1`)", CellIndex::new(row, column));
+                    }
+
+                    self.expr_cells.insert(
+                        CellIndex::new(row, column).with_sheet(sheet),
+                        Cell::Expression(content));
+                }
+                else
+                {
+                    self.value_cells.insert(
+                        CellIndex::new(row, column).with_sheet(sheet),
+                        Cell::Value(cell.to_string()));
+                }
+            }
+
+            row += 1;
+        }
+
+        self.loaded_values = self.value_cells.len();
+    }
+
+    // The name of a `=== Name ===` line, names start with a letter and only have letters, digits and `_`
+    fn sheet_header(line: &str) -> Option<&str>
+    {
+        let name = line.trim().strip_prefix("===")?.strip_suffix("===")?.trim();
+
+        if !Sheet::is_sheet_name(name)
+        {
+            panic!("Invalid sheet name `{}`, expected a letter followed by letters, digits or `_`", name);
+        }
+
+        Some(name)
+    }
+
+    pub fn is_sheet_name(name: &str) -> bool
+    {
+        name.starts_with(|c: char| c.is_ascii_alphabetic()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    // Index 0 is the unnamed sheet, the others are in the order they're declared, see `CellIndex::sheet`
+    pub fn sheet_names(&self) -> &[String]
+    {
+        &self.sheet_names
+    }
+
+    pub fn evaluate(&mut self) -> Result<(), EvalError>
+    {
+        self.evaluate_with(&mut vec![], &mut Profile::default(), false, DEFAULT_EPSILON, &mut StdRng::from_entropy(), None, &HashMap::new())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn evaluate_with(&mut self, visiting: &mut VisitingList, profile: &mut Profile, force_recalc: bool, epsilon: f64,
+        rng: &mut StdRng, cell_seed: Option<u64>, functions: &FunctionRegistry) -> Result<(), EvalError>
+    {
+        let mut formulas = Vec::<(CellIndex, Box<dyn Expression>)>::new();
+
+        for (index, cell) in &self.expr_cells
+        {
+            if let Cell::Expression(expr) = cell
+            {
+                let start = Instant::now();
+                let tokens = Tokenizer::new(expr.to_string()).in_cell(index, &self.sheet_names).with_functions(functions.keys()).get_tokens();
+                profile.tokenize += start.elapsed();
+
+                Sheet::check_parentheses(index, expr, &tokens);
+
+                let start = Instant::now();
+                formulas.push((index.clone(), Parser::new(tokens).parse()));
+                profile.parse += start.elapsed();
+            }
+        }
+
+        Sheet::sort_formulas(&mut formulas);
+
+        let context = &mut Context
+        {
+            expr_cells  : &self.expr_cells,
+            value_cells : &mut self.value_cells,
+            visiting,
+            force_recalc,
+            epsilon,
+            sheet_names : &self.sheet_names,
+            rng,
+            cell_seed,
+            functions,
+        };
+
+        for (index, expression) in formulas
+        {
+            // Already evaluated as a reference of another formula
+            if context.value_cells.contains_key(&index)
+            {
+                continue;
+            }
+
+            context.visiting.clear();
+            context.visiting.push(index.clone());
+
+            let start = Instant::now();
+            let evaluated = Sheet::evaluate_formula(&index, expression, context);
+            profile.evaluate += start.elapsed();
+
+            match evaluated
+            {
+                // The cell reads `#REF!`, the rest of the sheet is still evaluated
+                Ok(_) | Err(EvalError::UnknownCell(_) | EvalError::InvalidReference(_)) => (),
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Row-major order keeps the results (e.g. the reported cycle) the same between runs,
+    // spilling formulas go first, so that the cells they fill can be referenced
+    fn sort_formulas(formulas: &mut [(CellIndex, Box<dyn Expression>)]) -> ()
+    {
+        formulas.sort_by(|(a, _), (b, _)| a.cmp(b));
+        formulas.sort_by_key(|(_, expression)| !expression.spills());
+    }
+
+    // The splitmix64 finalizer, unlike `DefaultHasher` it's the same with every Rust version so a
+    // seed keeps giving the same values
+    fn mix_seed(seed: u64) -> u64
+    {
+        let mut mixed = seed.wrapping_add(0x9e3779b97f4a7c15);
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94d049bb133111eb);
+
+        mixed ^ (mixed >> 31)
+    }
+
+    // Evaluates the formula of `cell_index` and caches its value, a formula with a reference that
+    // can't be followed is cached as `#REF!` but still fails so its dependents are `#REF!` too
+    fn evaluate_formula(cell_index: &CellIndex, expression: Box<dyn Expression>,
+        context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        let result = match context.cell_seed
+        {
+            Some(seed) =>
+            {
+                let (row, column) = cell_index.get();
+                let cell_seed = [cell_index.sheet(), row, column].into_iter()
+                    .fold(seed, |mixed, part| Sheet::mix_seed(mixed ^ part as u64));
+
+                // Restored afterwards, so a formula referring to this one goes on with its own values
+                let shared = mem::replace(context.rng, StdRng::seed_from_u64(cell_seed));
+                let result = Sheet::evaluate_spilling(cell_index, expression, context);
+                *context.rng = shared;
+
+                result
+            },
+            None => Sheet::evaluate_spilling(cell_index, expression, context),
+        };
+
+        if let Err(EvalError::UnknownCell(_) | EvalError::InvalidReference(_)) = result
+        {
+            context.value_cells.insert(cell_index.clone(), Cell::Value(String::from(REF_ERROR)));
+
+            logging::debug!("evaluated {} to {}", cell_index, REF_ERROR);
+        }
+
+        result
+    }
+
+    // Evaluates the formula of `cell_index` and caches its value, a spilling formula also
+    // fills the cells to its right and fails if any of them isn't blank
+    fn evaluate_spilling(cell_index: &CellIndex, mut expression: Box<dyn Expression>,
+        context: &mut Context) -> Result<LiteralValue, EvalError>
+    {
+        let value = if expression.spills()
+        {
+            let values = expression.evaluate_range(context)?.unwrap();
+            let (row, column) = cell_index.get();
+
+            for (offset, value) in values.iter().enumerate().skip(1)
+            {
+                let target = CellIndex::new(row, column + offset).with_sheet(cell_index.sheet());
+
+                if !CellRef::is_blank(&target, context)
+                {
+                    panic!("Spill of `{}` is blocked by `{}`", cell_index, target);
+                }
+
+                context.value_cells.insert(target, Cell::from_literal(value));
+            }
+
+            values[0].clone()
+        }
+        else
+        {
+            expression.evaluate(context)?
+        };
+
+        context.value_cells.insert(cell_index.clone(), Cell::from_literal(&value));
+
+        logging::debug!("evaluated {} to {:?}", cell_index, value);
+
+        Ok(value)
+    }
+
+    // Like `evaluate_with`, but every formula is parsed and evaluated on its own and its failure is
+    // recorded instead of stopping the evaluation. A formula referring to a failing cell fails too
+    #[allow(clippy::too_many_arguments)]
+    fn diagnose(&mut self, visiting: &mut VisitingList, profile: &mut Profile, force_recalc: bool, epsilon: f64,
+        rng: &mut StdRng, cell_seed: Option<u64>, functions: &FunctionRegistry) -> Vec<Diagnostic>
+    {
+        let mut diagnostics = Vec::<Diagnostic>::new();
+        let mut formulas = Vec::<(CellIndex, Box<dyn Expression>)>::new();
+
+        let mut indexes: Vec<&CellIndex> = self.expr_cells.keys().collect();
+        indexes.sort();
+
+        let diagnostic = |index: &CellIndex, kind: DiagnosticKind, message: String| -> Diagnostic
+        {
+            Diagnostic
+            {
+                cell   : Some(index.clone()),
+                sheet  : self.sheet_names[index.sheet()].clone(),
+                kind,
+                message,
+            }
+        };
+
+        for index in indexes
+        {
+            if let Some(Cell::Expression(expr)) = self.expr_cells.get(index)
+            {
+                let parsed = catch_panic(||
+                {
+                    let start = Instant::now();
+                    let tokens = Tokenizer::new(expr.to_string()).in_cell(index, &self.sheet_names).with_functions(functions.keys()).get_tokens();
+                    profile.tokenize += start.elapsed();
+
+                    Sheet::check_parentheses(index, expr, &tokens);
+
+                    // Folding would evaluate the constant calls, so their failures would be parse failures
+                    let start = Instant::now();
+                    let expression = Parser::new(tokens).without_folding().parse();
+                    profile.parse += start.elapsed();
+
+                    expression
+                });
+
+                match parsed
+                {
+                    Ok(expression) => formulas.push((index.clone(), expression)),
+                    Err(message) => diagnostics.push(diagnostic(index, DiagnosticKind::Parse, message)),
+                }
+            }
+        }
+
+        Sheet::sort_formulas(&mut formulas);
+
+        let context = &mut Context
+        {
+            expr_cells  : &self.expr_cells,
+            value_cells : &mut self.value_cells,
+            visiting,
+            force_recalc,
+            epsilon,
+            sheet_names : &self.sheet_names,
+            rng,
+            cell_seed,
+            functions,
+        };
+
+        for (index, expression) in formulas
+        {
+            if context.value_cells.contains_key(&index)
+            {
+                continue;
+            }
+
+            context.visiting.clear();
+            context.visiting.push(index.clone());
+
+            let start = Instant::now();
+            let evaluated = catch_panic(|| Sheet::evaluate_formula(&index, expression, context));
+            profile.evaluate += start.elapsed();
+
+            match evaluated
+            {
+                // A reference that can't be followed isn't a failure, the cell reads `#REF!`
+                Ok(Ok(_)) | Ok(Err(EvalError::UnknownCell(_) | EvalError::InvalidReference(_))) => (),
+                Ok(Err(error @ EvalError::Cycle(_))) => diagnostics.push(diagnostic(&index, DiagnosticKind::Cycle, error.to_string())),
+                Ok(Err(error)) => diagnostics.push(diagnostic(&index, DiagnosticKind::Eval, error.to_string())),
+                Err(message) => diagnostics.push(diagnostic(&index, DiagnosticKind::Eval, message)),
+            }
+        }
+
+        diagnostics.sort_by(|a, b| a.cell.cmp(&b.cell));
+
+        diagnostics
+    }
+
+    // Tokenizes and parses every formula without evaluating it, panics on the first invalid one
+    pub fn validate(&self) -> ()
+    {
+        for (index, cell) in &self.expr_cells
+        {
+            if let Cell::Expression(expr) = cell
+            {
+                let tokens = Tokenizer::new(expr.to_string()).in_cell(index, &self.sheet_names).with_functions(&self.functions).get_tokens();
+
+                Sheet::check_parentheses(index, expr, &tokens);
+                // Folding would evaluate the constant calls, which can fail
+                Parser::new(tokens).without_folding().parse();
+            }
+        }
+    }
+
+    // Unbalanced parentheses would otherwise fail deep in the parser, or go unnoticed for an extra `)`
+    fn check_parentheses(index: &CellIndex, expr: &str, tokens: &[Token]) -> ()
+    {
+        let mut depth = 0usize;
+
+        for token in tokens
+        {
+            match token.get_type()
+            {
+                TokenType::OpeningParenthese => depth += 1,
+                TokenType::ClosingParenthese if depth == 0 =>
+                    panic!("Cell `{}` has a `)` without a matching `(`: `={}`", index, expr),
+                TokenType::ClosingParenthese => depth -= 1,
+                _ => (),
+            }
+        }
+
+        if depth > 0
+        {
+            panic!("Cell `{}` is missing {} `)`: `={}`", index, depth, expr);
+        }
+    }
+
+    // The parse tree of every formula, row-major and as written, constant subtrees aren't folded.
+    // A formula that can't be parsed gives its failure instead
+    pub fn syntax_trees(&self) -> Vec<SyntaxTree>
+    {
+        let mut indexes: Vec<&CellIndex> = self.expr_cells.keys().collect();
+        indexes.sort();
+
+        indexes.into_iter()
+            .filter_map(|index| match &self.expr_cells[index]
+            {
+                Cell::Expression(expr) => Some(SyntaxTree
+                {
+                    cell : index.clone(),
+                    sheet: self.sheet_names[index.sheet()].clone(),
+                    json : catch_panic(||
+                    {
+                        let tokens = Tokenizer::new(expr.to_string()).in_cell(index, &self.sheet_names).with_functions(&self.functions).get_tokens();
+
+                        Sheet::check_parentheses(index, expr, &tokens);
+                        Parser::new(tokens).without_folding().parse().to_json()
+                    }),
+                }),
+                Cell::Value(_) => None,
+            })
+            .collect()
+    }
+
+    // The cell of a label like in a reference (e.g. `A1` or `Prices!A1`), `None` for an invalid label or an unknown sheet
+    pub fn find_cell(&self, label: &str) -> Option<CellIndex>
+    {
+        let (sheet, label) = match label.trim().split_once('!')
+        {
+            Some((name, label)) => (self.sheet_names.iter().position(|sheet_name| !sheet_name.is_empty() && sheet_name.eq_ignore_ascii_case(name))?, label),
+            None => (0, label.trim()),
+        };
+
+        Some(CellIndex::from_label(label)?.with_sheet(sheet))
+    }
+
+    // The cells the formula of `index` refers to, each once in the order they're written (a range gives
+    // each of its cells), a value refers to none
+    pub fn dependencies(&self, index: &CellIndex) -> Vec<CellIndex>
+    {
+        let mut references = Vec::<CellIndex>::new();
+
+        if let Some(Cell::Expression(expr)) = self.expr_cells.get(index)
+        {
+            // Only the references are needed, folding would evaluate the constant calls
+            Parser::in_cell(expr, index, &self.sheet_names, &self.functions).without_folding().parse().collect_refs(&mut references, true);
+        }
+
+        let mut seen = BTreeSet::<CellIndex>::new();
+        references.retain(|reference| seen.insert(reference.clone()));
+
+        references
+    }
+
+    // The dependencies of `index` and theirs as an indented tree, a line per cell with its formula or value.
+    // A formula already explained is only named again, and a reference back to a cell being explained is a cycle
+    pub fn explain_dependencies(&self, index: &CellIndex) -> String
+    {
+        let mut lines = Vec::<String>::new();
+        self.explain_cell(index, &mut vec![], &mut BTreeSet::new(), &mut lines);
+
+        lines.join("\n")
+    }
+
+    fn explain_cell(&self, index: &CellIndex, path: &mut Vec<CellIndex>, explained: &mut BTreeSet<CellIndex>, lines: &mut Vec<String>) -> ()
+    {
+        let label = match self.sheet_names.get(index.sheet()).filter(|name| !name.is_empty())
+        {
+            Some(name) => format!("{}!{}", name, index),
+            None => index.to_string(),
+        };
+
+        let content = match (self.expr_cells.get(index), self.value_cells.get(index))
+        {
+            (Some(cell), _) => cell.display(),
+            (None, Some(Cell::Value(value))) if !value.trim().is_empty() => value.clone(),
+            (None, Some(_)) => String::from("(blank)"),
+            (None, None) => String::from("(undefined)"),
+        };
+
+        let note = if path.contains(index) { " (cycle)" } else if explained.contains(index) { " (see above)" } else { "" };
+
+        lines.push(format!("{}{}: {}{}", "  ".repeat(path.len()), label, content, note));
+
+        if !note.is_empty()
+        {
+            return;
+        }
+
+        if self.expr_cells.contains_key(index)
+        {
+            explained.insert(index.clone());
+        }
+
+        path.push(index.clone());
+
+        for dependency in self.dependencies(index)
+        {
+            self.explain_cell(&dependency, path, explained, lines);
+        }
+
+        path.pop();
+    }
+
+    // Flags references to blank or undefined cells, which would silently be taken as 0,
+    // cells of a range aren't flagged since ranges skip blanks, nor are the cells a formula spills to.
+    // A formula that can't be parsed is left to fail when it's evaluated
+    pub fn lint(&self) -> Vec<LintWarning>
+    {
+        let mut warnings = Vec::<LintWarning>::new();
+        let mut references = Vec::<(CellIndex, Vec<CellIndex>)>::new();
+        let mut spilling = Vec::<CellIndex>::new();
+
+        for (index, cell) in &self.expr_cells
+        {
+            if let Cell::Expression(expr) = cell
+            {
+                let parsed = catch_panic(|| Parser::in_cell(expr, index, &self.sheet_names, &self.functions).without_folding().parse());
+
+                if let Ok(expression) = parsed
+                {
+                    let mut cells = Vec::<CellIndex>::new();
+                    expression.collect_refs(&mut cells, false);
+
+                    if expression.spills()
+                    {
+                        spilling.push(index.clone());
+                    }
+
+                    references.push((index.clone(), cells));
+                }
+            }
+        }
+
+        for (index, cells) in references
+        {
+            for reference in cells
+            {
+                let (blank, undefined) = match self.value_cells.get(&reference)
+                {
+                    Some(Cell::Value(value)) => (value.trim().is_empty(), false),
+                    _ => (false, !self.expr_cells.contains_key(&reference)),
+                };
+
+                if (blank || undefined) && !self.is_spilled_to(&reference, &spilling)
+                {
+                    warnings.push(LintWarning
+                    {
+                        cell     : index.clone(),
+                        reference,
+                        undefined,
+                    });
+                }
+            }
+        }
+
+        warnings.sort_by(|a, b| (&a.cell, &a.reference).cmp(&(&b.cell, &b.reference)));
+
+        warnings
+    }
+
+    // Whether a spilling formula may fill `cell`: it's to the right of one of them in the same row, with
+    // only blank or undefined cells in between. Without evaluating, the number of values is unknown
+    fn is_spilled_to(&self, cell: &CellIndex, spilling: &[CellIndex]) -> bool
+    {
+        let (row, column) = cell.get();
+
+        spilling.iter().any(|formula|
+        {
+            let (formula_row, formula_column) = formula.get();
+
+            formula.sheet() == cell.sheet() && formula_row == row && formula_column < column
+                && (formula_column + 1..column).all(|between|
+                {
+                    let between = CellIndex::new(row, between).with_sheet(cell.sheet());
+
+                    !self.expr_cells.contains_key(&between)
+                        && self.value_cells.get(&between).is_none_or(|value| value.as_value().is_some_and(|value| value.trim().is_empty()))
+                })
+        })
+    }
+
+    pub fn write_to(&self, options: &FormatOptions, output: &mut impl Write) -> io::Result<()>
+    {
+        let Sheet { expr_cells, value_cells, sheet_names, .. } = self;
+
+        if value_cells.is_empty() && expr_cells.is_empty()
+        {
+            return Ok(());
+        }
+
+        // Built beforehand, so that the grid can borrow them like the other cells
+        let mut with_formulas = HashMap::<&CellIndex, Cell>::new();
+        if options.with_formulas && !options.emit_formulas
+        {
+            for (index, cell) in value_cells
+            {
+                if let Some(expr) = expr_cells.get(index)
+                {
+                    let value = Sheet::format_number(&cell.display(), index.get().1, options);
+                    with_formulas.insert(index, Cell::Value(format!("{} ({})", value, expr.display())));
+                }
+            }
+        }
+
+        // Formulas are still evaluated so that they get validated, those of a sheet that wasn't
+        // evaluated are written as they are
+        let sorted: Vec<(&CellIndex, &Cell)> = value_cells.iter()
+            .map(|(index, cell)|
+                (index, if options.emit_formulas { expr_cells.get(index).unwrap_or(cell) } else { with_formulas.get(index).unwrap_or(cell) }))
+            .chain(expr_cells.iter().filter(|(index, _)| !value_cells.contains_key(index)))
+            .collect();
+
+        Sheet::write_grid(sorted, |index| expr_cells.contains_key(index), sheet_names, options, output)
+    }
+
+    // Writes the cells as a grid, `is_formula` tells the cells whose value comes from a formula
+    fn write_grid(mut cells: Vec<(&CellIndex, &Cell)>, is_formula: impl Fn(&CellIndex) -> bool, sheet_names: &[String],
+        options: &FormatOptions, output: &mut impl Write) -> io::Result<()>
+    {
+        cells.sort_by(|a, b| a.0.cmp(b.0));
+
+        if options.trim_blank_rows
+        {
+            let filled = cells.iter()
+                .filter(|(_, cell)| !cell.display().trim().is_empty())
+                .map(|(index, _)| (index.sheet(), index.get().0))
+                .collect::<BTreeSet<(usize, usize)>>();
+
+            cells.retain(|(index, _)| filled.contains(&(index.sheet(), index.get().0)));
+        }
+
+        // Built beforehand like `with_formulas`, the projected grid borrows them
+        let blank = Cell::Value(String::new());
+        let mut missing = Vec::<CellIndex>::new();
+        if let Some(columns) = &options.columns
+        {
+            let rows = cells.iter().map(|(index, _)| (index.sheet(), index.get().0)).collect::<BTreeSet<(usize, usize)>>();
+            let present = cells.iter().map(|(index, _)| *index).collect::<BTreeSet<&CellIndex>>();
+
+            missing = rows.iter()
+                .flat_map(|&(sheet, row)| columns.iter().map(move |&column| CellIndex::new(row, column).with_sheet(sheet)))
+                .filter(|index| !present.contains(index))
+                .collect();
+        }
+
+        // Rebound, so that it can also borrow the local blank cells
+        let mut cells = cells;
+        if let Some(columns) = &options.columns
+        {
+            let position = |index: &CellIndex| columns.iter().position(|&column| column == index.get().1);
+
+            cells.retain(|(index, _)| position(index).is_some());
+            cells.extend(missing.iter().map(|index| (index, &blank)));
+            cells.sort_by_key(|(index, _)| (index.sheet(), index.get().0, position(index)));
+        }
+
+        // Only blank input cells take the null value, a formula giving an empty text doesn't
+        let display = |index: &CellIndex, cell: &Cell| -> String
+        {
+            match (&options.null_value, cell)
+            {
+                (Some(null_value), Cell::Value(val)) if val.trim().is_empty() && !is_formula(index) =>
+                    null_value.clone(),
+                (_, Cell::Value(val)) if !options.emit_formulas => Sheet::format_number(val, index.get().1, options),
+                _ => cell.display(),
+            }
+        };
+
+        // Auto-sizing needs a first pass to measure the widest value of each column of each sheet
+        let mut widths = HashMap::<(usize, usize), usize>::new();
+        if options.width == Width::Auto
+        {
+            for (index, cell) in &cells
+            {
+                let (_, column) = index.get();
+                let width = widths.entry((index.sheet(), column)).or_insert(0);
+                *width = (*width).max(display(index, cell).chars().count());
+            }
+        }
+        
+        let mut last_line = None::<(usize, usize)>;
+        for (index, cell) in cells
+        {
+            let (row, column) = index.get();
+            let line = (index.sheet(), row);
+
+            if last_line.is_some_and(|last_line| last_line != line)
+            {
+                writeln!(output)?;
+            }
+
+            // Named sheets are written with their header, so the output can be read back
+            if last_line.map(|(sheet, _)| sheet) != Some(index.sheet())
+            {
+                let name = sheet_names.get(index.sheet()).filter(|name| !name.is_empty())
+                    .or(options.sheet_name.as_ref().filter(|_| index.sheet() == 0));
+
+                if let Some(name) = name
+                {
+                    writeln!(output, "=== {} ===", name)?;
+                }
+            }
+
+            last_line = Some(line);
+
+            let width = match options.width
+            {
+                Width::Fixed(width) => width,
+                Width::Auto         => widths[&(index.sheet(), column)],
+            };
+
+            let padded = options.alignment.for_value(&cell.display()).pad(&display(index, cell), width);
+
+            // Colored after padding, so escape codes don't count toward the width
+            if options.color && is_formula(index)
+            {
+                write!(output, "{}{}{}|", FORMULA_COLOR, padded, RESET_COLOR)?;
+            }
+            else
+            {
+                write!(output, "{}|", padded)?;
+            }
+        }
+
+        writeln!(output)?;
+
+        Ok(())
+    }
+
+    // Only changes how a number is written, the other cells are still evaluated with its full value
+    fn format_number(value: &str, column: usize, options: &FormatOptions) -> String
+    {
+        if let (Some(number_format), Ok(f)) = (options.column_formats.get(&column), value.parse::<f64>())
+        {
+            return number_format.format(f);
+        }
+
+        let rounded = match (options.round_output, value.parse::<f64>())
+        {
+            // Adding 0 turns a negative number rounded to zero (e.g. `-0.004`) into `0` instead of `-0`
+            // Integers have nothing to round, and would lose their exact digits as floats
+            (Some(decimals), Ok(f)) if f.is_finite() && value.parse::<i64>().is_err() => Cell::number(format!("{:.*}", decimals, f).parse::<f64>().unwrap() + 0.0),
+            _ => value.to_string(),
+        };
+
+        Sheet::group_thousands(&rounded, options.thousands_separator)
+    }
+
+    // Inserts `separator` between each group of three digits of the integer part of a number
+    // (e.g. `-1234567.5` as `-1,234,567.5`), anything else is kept as it is
+    fn group_thousands(value: &str, separator: Option<char>) -> String
+    {
+        let separator = match separator
+        {
+            Some(separator) if value.parse::<f64>().is_ok_and(f64::is_finite) => separator,
+            _ => return value.to_string(),
+        };
+
+        let (sign, unsigned) = value.split_at(value.find(|c: char| c.is_ascii_digit()).unwrap_or(0));
+        let digits = unsigned.find(|c: char| !c.is_ascii_digit()).unwrap_or(unsigned.len());
+        let (integer, rest) = unsigned.split_at(digits);
+
+        let mut grouped = String::from(sign);
+
+        for (i, digit) in integer.chars().enumerate()
+        {
+            if i > 0 && (integer.len() - i) % 3 == 0
+            {
+                grouped.push(separator);
+            }
+
+            grouped.push(digit);
+        }
+
+        grouped + rest
+    }
+
+    // Row-major, formula cells only have a value once the sheet is evaluated
+    pub fn cells(&self) -> impl Iterator<Item = (CellIndex, LiteralValue)> + '_
+    {
+        let mut sorted: Vec<(&CellIndex, &Cell)> = self.value_cells.iter().collect();
+        sorted.sort_by(|a, b| a.0.cmp(b.0));
+
+        sorted.into_iter()
+            .map(|(index, cell)|
+            {
+                let value = match cell
+                {
+                    Cell::Value(value) => Cell::literal(value),
+                    Cell::Expression(_) => unreachable!(),
+                };
+
+                (index.clone(), value)
+            })
+    }
+
+    // The cells whose values differ in `other`, row-major (e.g. to compare a sheet before and after a change).
+    // Sheets are matched by position, blank cells count as missing and numbers are equal within a relative
+    // `epsilon` like for `=`, texts have to be exactly the same
+    pub fn diff(&self, other: &Sheet, epsilon: f64) -> Vec<CellDiff>
+    {
+        let non_blank = |sheet: &Sheet| sheet.cells()
+            .filter(|(_, value)| !matches!(value, LiteralValue::Text(text) if text.trim().is_empty()))
+            .collect::<BTreeMap<CellIndex, LiteralValue>>();
+
+        let (mut old_cells, mut new_cells) = (non_blank(self), non_blank(other));
+        let indexes = old_cells.keys().chain(new_cells.keys()).cloned().collect::<BTreeSet<CellIndex>>();
+
+        indexes.into_iter()
+            .filter_map(|cell|
+            {
+                let (old, new) = (old_cells.remove(&cell), new_cells.remove(&cell));
+
+                let equal = match (&old, &new)
+                {
+                    (Some(LiteralValue::Text(a)), Some(LiteralValue::Text(b))) => a == b,
+                    (Some(a @ (LiteralValue::Float(_) | LiteralValue::Int(_))), Some(b @ (LiteralValue::Float(_) | LiteralValue::Int(_)))) =>
+                        Comparison::Equal.compare(a, b, epsilon),
+                    _ => false,
+                };
+
+                if equal
+                {
+                    return None;
+                }
+
+                let sheet_names = if new.is_some() { &other.sheet_names } else { &self.sheet_names };
+
+                Some(CellDiff
                 {
-                    match self.1.remove(0).evaluate(expr_cells, value_cells, visiting)
-                    {
-                        LiteralValue::Float(f) => f,
-                        _ => panic!("Expected numbers as `if` params")
-                    }
-                };
+                    sheet: sheet_names.get(cell.sheet()).cloned().unwrap_or_default(),
+                    cell,
+                    old,
+                    new,
+                })
+            })
+            .collect()
+    }
 
-                LiteralValue::Float(if first != 0.0 { second } else { third })
-            },
-            "vlookup" =>
+    // Inside a formula, `|` within parentheses or text isn't a delimiter (e.g. `=if(A0 || B0, 1, 0)`),
+    // neither are tabs when they're delimiters too. `||` is always the operator in a formula (`=A0||B0`
+    // is one cell), a blank cell after a formula needs a space (`=A0| |5`)
+    fn split_columns(line: &str, tab_width: bool) -> Vec<&str>
+    {
+        let mut columns = Vec::<&str>::new();
+        let mut start = 0;
+        let mut depth = 0usize;
+        let mut in_text = false;
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next()
+        {
+            let is_formula = line[start..].starts_with('=');
+
+            match c
             {
-                todo!();
-            },
-            "concatenate" =>
+                '"' if is_formula => in_text = !in_text,
+                '(' if is_formula && !in_text => depth += 1,
+                ')' if is_formula && !in_text => depth = depth.saturating_sub(1),
+                '|' if is_formula && !in_text && chars.peek().is_some_and(|&(_, next)| next == '|') =>
+                {
+                    chars.next();
+                },
+                '|' | '\t' if (c == '|' || tab_width) && depth == 0 && !in_text =>
+                {
+                    columns.push(&line[start..i]);
+                    start = i + 1;
+                },
+                _ => ()
+            }
+        }
+
+        columns.push(&line[start..]);
+
+        columns
+    }
+
+    // `rows` and `columns` are the size of the bounding rectangle, not the number of populated cells.
+    // `values` are the value cells as written (blank ones too), the same before and after evaluating
+    pub fn dimensions(&self) -> Dimensions
+    {
+        let (rows, columns) = self.expr_cells.keys()
+            .chain(self.value_cells.keys())
+            .fold((0, 0), |(rows, columns), index|
             {
-                todo!();
-            },
-            _ => todo!("Not all FUNCTIONS are implemented")
+                let (row, column) = index.get();
+                (rows.max(row + 1), columns.max(column + 1))
+            });
+
+        Dimensions
+        {
+            rows,
+            columns,
+            values  : self.loaded_values,
+            formulas: self.expr_cells.len(),
         }
     }
 }
 
-struct Literal(Token);
-
-impl Literal
+// Formats cells the way a sheet is written, e.g. to format the values of an evaluated sheet in several
+// ways. `Cell::Expression` cells are written as formulas, blank values take the null value
+pub fn format_grid(cells: &Table, options: &FormatOptions) -> String
 {
-    pub fn new(literal: Token) -> Self
+    let mut output = Vec::<u8>::new();
+
+    // Writing to a `Vec` can't fail and only valid UTF-8 gets written
+    if !cells.is_empty()
     {
-        Literal(literal)
+        Sheet::write_grid(cells.iter().collect(), |index| cells.get(index).is_some_and(Cell::is_formula), &[], options, &mut output).unwrap();
     }
+
+    String::from_utf8(output).unwrap()
 }
 
-impl Expression for Literal
+// Evaluates a formula on its own (e.g. for a calculator), with or without its `=`. Without a sheet
+// it can't refer to any cell, other failures panic like in a sheet
+pub fn eval_expression(input: &str) -> Result<LiteralValue, EvalError>
+{
+    eval_expression_in(input, &Table::new())
+}
+
+// Like `eval_expression`, but the formula can refer to `cells` as if they were a sheet (e.g. the
+// cells set in a REPL), an empty table is no sheet at all
+pub fn eval_expression_in(input: &str, cells: &Table) -> Result<LiteralValue, EvalError>
 {
-    fn evaluate(&mut self, _expr_cells: &Table, _value_cells: &mut Table, _visiting: &mut VisitingList) -> LiteralValue
+    let input = input.trim();
+    let mut expression = Parser::from(input.strip_prefix('=').unwrap_or(input)).parse();
+
+    if cells.is_empty()
     {
-        match self.0.get_type()
+        let mut references = Vec::<CellIndex>::new();
+        expression.collect_refs(&mut references, true);
+
+        if let Some(reference) = references.into_iter().next()
         {
-            TokenType::Number => self.0.literal.take().unwrap(),
-            _ => todo!()
+            return Err(EvalError::NoSheet(reference));
         }
     }
+
+    let context = &mut Context
+    {
+        expr_cells  : cells,
+        value_cells : &mut HashMap::new(),
+        visiting    : &mut vec![],
+        force_recalc: false,
+        epsilon     : DEFAULT_EPSILON,
+        sheet_names : &[],
+        rng         : &mut StdRng::from_entropy(),
+        cell_seed   : None,
+        functions   : &HashMap::new(),
+    };
+
+    // Like in a cell, a spilling formula gives its first value
+    if expression.spills()
+    {
+        return Ok(expression.evaluate_range(context)?.unwrap().remove(0));
+    }
+
+    expression.evaluate(context)
 }
 
-pub struct CellRef(Token);
+// Time spent in each phase of the last evaluation, referenced cells are re-parsed while
+// being evaluated so their tokenizing and parsing counts towards `evaluate`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Profile
+{
+    pub load    : Duration,
+    pub tokenize: Duration,
+    pub parse   : Duration,
+    pub evaluate: Duration,
+}
 
-impl CellRef
+impl fmt::Display for Profile
 {
-    pub fn new(token: Token) -> Self
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
-        return CellRef(token);
+        writeln!(f, "load:     {:?}", self.load)?;
+        writeln!(f, "tokenize: {:?}", self.tokenize)?;
+        writeln!(f, "parse:    {:?}", self.parse)?;
+        write!(f, "evaluate: {:?}", self.evaluate)
     }
+}
 
-    pub fn text_to_number(column_name: String) -> usize
-    {
-        let column_name = column_name.to_uppercase();
+// Keeps its buffers between sheets, so evaluating many small sheets doesn't reallocate them
+pub struct Evaluator
+{
+    sheet        : Sheet,
+    visiting     : VisitingList,
+    profile      : Profile,
+    force_recalc : bool,
+    load_options : LoadOptions,
+    epsilon      : f64,
+    seed         : Option<u64>,
+    seed_per_cell: bool,
+    functions    : FunctionRegistry,
+    fail_fast    : bool,
+}
 
-        let mut sum: usize = 0;
-        let ac = 'A' as usize;
-    
-        for i in 0..column_name.len()
+impl Default for Evaluator
+{
+    fn default() -> Self
+    {
+        Evaluator
         {
-            sum *= 26;
-            sum += (column_name.chars().nth(i).unwrap() as usize) - ac + 1;
+            sheet        : Sheet::default(),
+            visiting     : VisitingList::new(),
+            profile      : Profile::default(),
+            force_recalc : false,
+            load_options : LoadOptions::default(),
+            epsilon      : DEFAULT_EPSILON,
+            seed         : None,
+            seed_per_cell: false,
+            functions    : HashMap::new(),
+            fail_fast    : false,
         }
-    
-        return sum - 1;
     }
+}
 
-    pub fn number_to_text(column: usize) -> String
+impl Evaluator
+{
+    pub fn new() -> Self
     {
-        let mut f = column as f32;
-        let mut s = String::new();
+        Evaluator::default()
+    }
+
+    pub fn reset(&mut self) -> ()
+    {
+        self.sheet.expr_cells.clear();
+        self.sheet.value_cells.clear();
+        self.sheet.functions = self.functions.keys().cloned().collect();
+        self.visiting.clear();
+        self.profile = Profile::default();
+    }
 
-        loop
+    pub fn evaluate(&mut self, file_content: &str) -> Result<&Sheet, EvalError>
+    {
+        if !self.fail_fast
         {
-            let c = f % 26.0;
-            s.insert(0, std::char::from_u32((if (c as u32) == 0 { 26 } else { c as u32 }) + 64).unwrap());
+            let diagnostics = self.diagnose(file_content);
 
-            let old = f;
-            f = f32::ceil(f / 26.0) - 1.0;
+            if !diagnostics.is_empty()
+            {
+                return Err(EvalError::Failures(diagnostics));
+            }
 
-            if old <= 26.0 { break; }
+            return Ok(&self.sheet);
         }
 
-        s
-    }
-}
+        self.reset();
 
-impl Expression for CellRef
-{
-    fn evaluate(&mut self, expr_cells: &Table, value_cells: &mut Table, visiting: &mut VisitingList) -> LiteralValue
-    {
-        if let LiteralValue::CellRef(cell_index) = self.0.literal
-            .as_ref()
-            .unwrap()
-        {
-            let (row, column) = cell_index.get();
+        let start = Instant::now();
+        self.sheet.load(file_content, &self.load_options);
+        self.profile.load = start.elapsed();
 
-            let cell_index = CellIndex::new(row, column);
+        let mut rng = self.rng();
+        let cell_seed = self.cell_seed();
+        self.sheet.evaluate_with(&mut self.visiting, &mut self.profile, self.force_recalc, self.epsilon, &mut rng, cell_seed, &self.functions)?;
 
-            let cell = value_cells
-                .get(&cell_index)
-                .or(expr_cells.get(&cell_index))
-                .expect("Refering to an unknown cell");
+        Ok(&self.sheet)
+    }
 
-            match cell
-            {
-                Cell::Expression(expr) =>
-                {
-                    if visiting.iter().find(|x| *x == &cell_index).is_some()
-                    {
-                        let mut path = String::new();
+    // By default a cell calling `random` or `randbetween` keeps its first value for every
+    // reference, forcing recalculation gives it a new value on each reference instead
+    pub fn set_force_recalc(&mut self, force_recalc: bool) -> ()
+    {
+        self.force_recalc = force_recalc;
+    }
 
-                        for item in (&visiting).iter()
-                        {
-                            let (row, column) = item.get();
+    // See `Sheet::with_header_rows`
+    pub fn set_header_rows(&mut self, header_rows: usize) -> ()
+    {
+        self.load_options.header_rows = header_rows;
+    }
 
-                            path.push_str(&(CellRef::number_to_text(row) + &column.to_string() + " -> "));
-                        }
-                        let (row, column) = &visiting.first().unwrap().get();
+    // See `Sheet::with_options`
+    pub fn set_tab_width(&mut self, tab_width: bool) -> ()
+    {
+        self.load_options.tab_width = tab_width;
+    }
 
-                        path.push_str(&(CellRef::number_to_text(*row) + &column.to_string()));
+    // The relative difference below which `=` and `<>` take two numbers as equal, `0` compares them exactly
+    pub fn set_epsilon(&mut self, epsilon: f64) -> ()
+    {
+        self.epsilon = epsilon;
+    }
 
-                        panic!("Cycle detected, {:?}", path)
-                    }
+    // By default every failing cell is evaluated and returned in `EvalError::Failures`, so one failure doesn't
+    // hide the others. Failing fast stops at the first one: a cycle is returned as `EvalError::Cycle`,
+    // other failures panic
+    pub fn set_fail_fast(&mut self, fail_fast: bool) -> ()
+    {
+        self.fail_fast = fail_fast;
+    }
 
-                    visiting.push(cell_index.clone());
+    // Random functions give the same values on every evaluation with the same seed
+    pub fn set_seed(&mut self, seed: Option<u64>) -> ()
+    {
+        self.seed = seed;
+    }
 
-                    let tokenizer = Tokenizer::new(expr.to_string());
-                    let mut parser: Parser = Parser::new(tokenizer.get_tokens());
-                    let mut expression = parser.parse();
+    // Makes `name` usable in the formulas of the sheets this evaluates, ignoring case like the built-in
+    // functions. Like them, a failure may panic instead of returning an error.
+    // Only letters are allowed, a trailing number would make it a cell reference
+    pub fn register_function(&mut self, name: &str, function: impl Fn(&[LiteralValue]) -> Result<LiteralValue, EvalError> + 'static) -> ()
+    {
+        let name = name.to_ascii_lowercase();
 
-                    let evaluated = expression.evaluate(expr_cells, value_cells, visiting);
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic())
+        {
+            panic!("Invalid function name `{}`, expected only letters", name);
+        }
 
-                    visiting.remove(
-                        visiting
-                                .iter()
-                                .position(|x| *x == cell_index)
-                                .unwrap());
+        // Constant arguments are folded with the built-in function while parsing, before the registered one is known
+        if FUNCTIONS.contains(&name.as_str())
+        {
+            panic!("Function `{}` is built in and can't be registered", name);
+        }
 
-                    value_cells.insert(cell_index,
-                        match evaluated
-                        {
-                            LiteralValue::Float(f) => Cell::Value(f.to_string()),
-                            _ => unreachable!()
-                        });
+        self.functions.insert(name, Box::new(function));
+    }
 
-                    return evaluated;
-                },
-                Cell::Value(value) =>
-                {
-                    // This should be changed if string literals will be supported
-                    return LiteralValue::Float(value.parse::<f32>().unwrap_or_default());
-                }
-            }
-        }
+    // Each formula draws from its own generator seeded from the seed and its cell, so a cell keeps its
+    // values when other cells are added or removed. Only with a seed
+    pub fn set_seed_per_cell(&mut self, seed_per_cell: bool) -> ()
+    {
+        self.seed_per_cell = seed_per_cell;
+    }
 
-        unreachable!()
+    fn cell_seed(&self) -> Option<u64>
+    {
+        self.seed.filter(|_| self.seed_per_cell)
     }
-}
 
-struct Group(Box<dyn Expression>);
+    fn rng(&self) -> StdRng
+    {
+        match self.seed
+        {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
 
-impl Group
-{
-    pub fn new(literal: Box<dyn Expression>) -> Self
+    pub fn profile(&self) -> &Profile
     {
-        Group(literal)
+        &self.profile
     }
-}
 
-impl Expression for Group
-{
-    fn evaluate(&mut self, expr_cells: &Table, value_cells: &mut Table, visiting: &mut VisitingList) -> LiteralValue
+    // Every failure of the sheet instead of only the first one, see `Sheet::diagnose`
+    pub fn diagnose(&mut self, file_content: &str) -> Vec<Diagnostic>
     {
-        self.0.evaluate(expr_cells, value_cells, visiting)
+        self.reset();
+
+        let start = Instant::now();
+        let loaded = catch_panic(|| self.sheet.load(file_content, &self.load_options));
+        self.profile.load = start.elapsed();
+
+        let mut rng = self.rng();
+        let cell_seed = self.cell_seed();
+        match loaded
+        {
+            Ok(()) => self.sheet.diagnose(&mut self.visiting, &mut self.profile, self.force_recalc, self.epsilon, &mut rng, cell_seed, &self.functions),
+            Err(message) => vec![Diagnostic
+            {
+                cell   : None,
+                sheet  : String::new(),
+                kind   : DiagnosticKind::Load,
+                message,
+            }],
+        }
     }
 }
 
-#[derive(Debug)]
-pub enum Cell
+pub struct Parser
 {
-    Value(String),
-    Expression(String),
+    tokens   : Vec<Token>,
+    // How many arguments of `INSPECTING_FUNCTIONS` are being parsed, nothing is folded within them
+    inspected: usize,
+    folding  : bool,
 }
 
-pub struct Parser
+/// Tokenizes a formula (without its `=`) and builds its parser in one step:
+///
+/// ```
+/// use mini_excel::parsing::Parser;
+/// use mini_excel::scanning::LiteralValue;
+///
+/// assert!(matches!(Parser::from("2 + 3 * 4").try_eval_const(), Some(LiteralValue::Int(14))));
+/// ```
+impl From<&str> for Parser
 {
-    tokens: Vec<Token>,
+    fn from(expression: &str) -> Self
+    {
+        Parser::new(Tokenizer::new(expression.to_string()).get_tokens())
+    }
 }
 
 impl Parser
@@ -489,98 +3749,108 @@ impl Parser
     {
         return Parser
         {
-            tokens
+            tokens,
+            inspected: 0,
+            folding  : true,
         };
     }
 
+    // Like `Parser::from`, with the references resolved from the cell of the formula (see `Tokenizer::in_cell`)
+    // and the registered functions known
+    fn in_cell<'n>(expression: &str, cell: &CellIndex, sheet_names: &[String], functions: impl IntoIterator<Item = &'n String>) -> Self
+    {
+        Parser::new(Tokenizer::new(expression.to_string()).in_cell(cell, sheet_names).with_functions(functions).get_tokens())
+    }
+
+    // Keeps the constant subtrees as they're written instead of replacing them by their value (e.g. `2*3`)
+    pub fn without_folding(mut self) -> Self
+    {
+        self.folding = false;
+        self
+    }
+
     pub fn parse_file(file_content: String) -> String
     {
-        let mut expr_cells = HashMap::<CellIndex, Cell>::new();
-        let mut value_cells = HashMap::<CellIndex, Cell>::new();
+        Parser::parse_file_with_options(file_content, &FormatOptions::default())
+    }
 
-        let lines = file_content.split('\n').collect::<Vec<&str>>();
+    pub fn parse_file_with_options(file_content: String, options: &FormatOptions) -> String
+    {
+        let mut output = Vec::<u8>::new();
 
-        for (row, line) in lines.iter().enumerate()
-        {
-            let columns = line.split('|').collect::<Vec<&str>>();
+        // Writing to a `Vec` can't fail and only valid UTF-8 gets written, only evaluating can
+        Parser::parse_file_to_writer(file_content, options, &mut output).unwrap_or_else(|error| panic!("{}", error));
 
-            for (column, cell) in columns.iter().enumerate()
-            {
-                if cell.starts_with('=')
-                {
-                    let mut content = cell.to_string();
-                    content.remove(0); // Delete '='
-                    expr_cells.insert(
-                        CellIndex::new(row, column),
-                        Cell::Expression(content));
-                }
-                else
-                {
-                    value_cells.insert(
-                        CellIndex::new(row, column),
-                        Cell::Value(cell.to_string()));
-                }
-            }
-        }
+        String::from_utf8(output).unwrap()
+    }
 
-        for (index, cell) in &expr_cells
-        {
-            match cell
-            {
-                Cell::Expression(expr) =>
-                {
-                    let tokenizer = Tokenizer::new(expr.to_string());
-                    let tokens = tokenizer.get_tokens();
-                    let mut parser = Parser::new(tokens);
-                    let mut expression = parser.parse();
-                    let b =  expression.evaluate(&expr_cells, &mut value_cells, &mut vec![]);
-                    value_cells.insert((*index).clone(), Cell::Value(
-                        match b
-                        {
-                            LiteralValue::Float(f) => f.to_string(),
-                            _ => unreachable!()
-                        }));
-                },
-                _ => ()
-            };
-        }
+    pub fn parse_file_to_writer(file_content: String, options: &FormatOptions, output: &mut impl Write) -> io::Result<()>
+    {
+        Evaluator::new().evaluate(&file_content)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?
+            .write_to(options, output)
+    }
 
-        let mut sorted: Vec<(&CellIndex, &Cell)> = value_cells.iter().collect();
-        sorted.sort_by(|a, b| a.0.cmp(b.0));
-        
-        let mut output = String::new();
-        let mut last_line = 0;
-        for (index, cell) in sorted
-        {
-            let (row, _) = index.get();
-            if row != last_line
-            {
-                last_line = row;
-                output += "\n";
-            }
+    pub fn try_eval_const(&mut self) -> Option<LiteralValue>
+    {
+        self.parse().try_eval_const()
+    }
 
-            match cell
-            {
-                Cell::Value(val)       => output.push_str(&format!("{: <10}", val)),
-                Cell::Expression(expr) => output.push_str(&format!("{: <10}", expr)),
-            };
+    fn parse(&mut self) -> Box<dyn Expression>
+    {
+        let expression = self.expression();
 
-            output += "|";
-        }
+        logging::debug!("parsed {:?}", expression);
 
-        output += "\n";
+        expression
+    }
 
-        output
+    fn expression(&mut self) -> Box<dyn Expression>
+    {
+        self.or()
     }
 
-    fn parse(&mut self) -> Box<dyn Expression>
+    fn or(&mut self) -> Box<dyn Expression>
+    {
+        let mut expr = self.and();
+
+        while self.next_token_is(&[TokenType::Or])
+        {
+            let op = self.consume();
+            let right = self.and();
+            expr = self.fold(Box::new(Binary::new(expr, op, right)));
+        }
+
+        expr
+    }
+
+    fn and(&mut self) -> Box<dyn Expression>
     {
-        self.expression()
+        let mut expr = self.comparison();
+
+        while self.next_token_is(&[TokenType::And])
+        {
+            let op = self.consume();
+            let right = self.comparison();
+            expr = self.fold(Box::new(Binary::new(expr, op, right)));
+        }
+
+        expr
     }
 
-    fn expression(&mut self) -> Box<dyn Expression>
+    fn comparison(&mut self) -> Box<dyn Expression>
     {
-        self.term()
+        let mut expr = self.term();
+
+        while self.next_token_is(&[TokenType::Equal, TokenType::NotEqual,
+            TokenType::Less, TokenType::LessEqual, TokenType::Greater, TokenType::GreaterEqual])
+        {
+            let op = self.consume();
+            let right = self.term();
+            expr = self.fold(Box::new(Binary::new(expr, op, right)));
+        }
+
+        expr
     }
 
     fn term(&mut self) -> Box<dyn Expression>
@@ -591,7 +3861,7 @@ impl Parser
         {
             let op = self.consume();
             let right = self.factor();
-            expr = Box::new(Binary::new(expr, op, right));
+            expr = self.fold(Box::new(Binary::new(expr, op, right)));
         }
 
         expr
@@ -605,7 +3875,7 @@ impl Parser
         {
             let op = self.consume();
             let right = self.unary();
-            expr = Box::new(Binary::new(expr, op, right));
+            expr = self.fold(Box::new(Binary::new(expr, op, right)));
         }
 
         expr
@@ -613,26 +3883,56 @@ impl Parser
 
     fn unary(&mut self) -> Box<dyn Expression>
     {
-        if self.next_token_is(&[TokenType::Plus, TokenType::Minus])
+        if self.next_token_is(&[TokenType::Plus, TokenType::Minus, TokenType::Bang])
         {
             let op = self.consume();
             let expression = self.unary();
-            return Box::new(Unary::new(op, expression));
+            return self.fold(Box::new(Unary::new(op, expression)));
         }
 
-        self.primary()
+        self.power()
+    }
+
+    // `^` binds tighter than a unary sign (`-2^2` is `-(2^2)`) and is right associative (`2^2^3` is `2^(2^3)`),
+    // its exponent may still be signed (`2^-1`)
+    fn power(&mut self) -> Box<dyn Expression>
+    {
+        let expr = self.primary();
+
+        if self.next_token_is(&[TokenType::Caret])
+        {
+            let op = self.consume();
+            let right = self.unary();
+            return self.fold(Box::new(Binary::new(expr, op, right)));
+        }
+
+        expr
     }
 
     fn primary(&mut self) -> Box<dyn Expression>
     {
-        if self.next_token_is(&[TokenType::Number])
+        if self.next_token_is(&[TokenType::Number, TokenType::Text, TokenType::Boolean])
         {
             return Box::new(Literal::new(self.consume()));
         }
 
         if self.next_token_is(&[TokenType::CellRef])
         {
-            return Box::new(CellRef::new(self.consume()));
+            let cell_ref = self.consume();
+
+            if self.next_token_is(&[TokenType::Colon])
+            {
+                self.consume(); // Consume ':'
+
+                if !self.next_token_is(&[TokenType::CellRef])
+                {
+                    panic!("Expected a cell reference after ':'");
+                }
+
+                return Box::new(Range::new(cell_ref, self.consume()));
+            }
+
+            return Box::new(CellRef::new(cell_ref));
         }
         
         if self.next_token_is(&[TokenType::OpeningParenthese])
@@ -648,7 +3948,7 @@ impl Parser
 
             self.consume(); // Consume ')'
 
-            return Box::new(group);
+            return self.fold(Box::new(group));
         }
 
         if self.next_token_is(&[TokenType::Function])
@@ -662,6 +3962,12 @@ impl Parser
 
             self.consume(); // Consume '('
 
+            let inspected = INSPECTING_FUNCTIONS.contains(&name.to_ascii_lowercase().as_str());
+            if inspected
+            {
+                self.inspected += 1;
+            }
+
             let mut params = Vec::<Box<dyn Expression>>::new();
 
             if !self.next_token_is(&[TokenType::ClosingParenthese])
@@ -677,12 +3983,33 @@ impl Parser
 
             self.consume(); // Consume ')'
 
-            return Box::new(FnExpression::new(name, params));
+            if inspected
+            {
+                self.inspected -= 1;
+            }
+
+            return self.fold(Box::new(FnExpression::new(name, params)));
         }
 
         panic!("Invalid expression: {}", self.consume().get_lexeme());
     }
 
+    // Replaces a constant subtree by its value, so it isn't recomputed on every evaluation. A subtree
+    // failing (e.g. `find("z", "abc")`) is kept to fail when the formula is evaluated, not parsed
+    fn fold(&self, expression: Box<dyn Expression>) -> Box<dyn Expression>
+    {
+        if !self.folding || self.inspected > 0
+        {
+            return expression;
+        }
+
+        match catch_panic(|| expression.try_eval_const())
+        {
+            Ok(Some(value)) => Box::new(Literal::from_value(value)),
+            Ok(None) | Err(_) => expression,
+        }
+    }
+
     fn consume(&mut self) -> Token
     {
         self.tokens.remove(0)
@@ -695,10 +4022,10 @@ impl Parser
             return false;
         }
 
-        let next = self.tokens.iter().nth(0);
+        let next = self.tokens.first();
         if let Some(n) = next
         {
-            if types.contains(&n.get_type())
+            if types.contains(n.get_type())
             {
                 return true;
             }
@@ -0,0 +1,1441 @@
+use super::*;
+
+fn render(value: LiteralValue) -> String
+{
+    match value
+    {
+        LiteralValue::Float(value) => value.to_string(),
+        LiteralValue::Int(value) => value.to_string(),
+        LiteralValue::Text(text) => text,
+        LiteralValue::CellRef(index) => index.to_string(),
+    }
+}
+
+// The value of a formula evaluated on its own, as it's written in a cell
+fn eval(formula: &str) -> String
+{
+    render(eval_expression(formula).unwrap())
+}
+
+// The values of an evaluated sheet, row-major and without the blank cells
+fn values(sheet: &Sheet) -> Vec<String>
+{
+    sheet.cells().map(|(_, value)| render(value)).filter(|value| !value.is_empty()).collect()
+}
+
+// The values of a sheet, each row split into its trimmed cells
+fn rows(sheet: &str) -> Vec<Vec<String>>
+{
+    Parser::parse_file(sheet.to_string())
+        .lines()
+        .map(|line| line.trim_end_matches('|').split('|').map(|cell| cell.trim().to_string()).collect())
+        .collect()
+}
+
+#[test]
+fn row_and_column_of_the_formula() -> ()
+{
+    assert_eq!(rows("=row()|=column()|5\n1|=row()*10+column()"), vec![vec!["1", "2", "5"], vec!["1", "22"]]);
+}
+
+#[test]
+fn row_and_column_of_a_referenced_formula_are_its_own() -> ()
+{
+    // `B0` reads the position of `A2`, not its own
+    assert_eq!(rows("1|2|=row()*10+column()\n=A2"), vec![vec!["1", "2", "13"], vec!["13"]]);
+}
+
+#[test]
+fn offset_to_a_neighbor() -> ()
+{
+    assert_eq!(rows("1|2|3\n4|5|=offset(A0, 1, 1)|=offset(B2, -1, -2)"), vec![vec!["1", "2", "3"], vec!["4", "5", "5", "1"]]);
+}
+
+#[test]
+fn offset_out_of_the_sheet_is_ref_error() -> ()
+{
+    // Before the first row, and past the last column
+    assert_eq!(rows("1|=offset(A0, -1, 0)|=offset(A0, 0, 9)|=A0"), vec![vec!["1", "#REF!", "#REF!", "1"]]);
+}
+
+#[test]
+#[should_panic(expected = "Expected a cell reference as the first `offset` param")]
+fn offset_of_a_number_fails() -> ()
+{
+    rows("=offset(1, 0, 0)");
+}
+
+#[test]
+fn indirect_of_a_label() -> ()
+{
+    assert_eq!(rows("7|=indirect(\"A0\")*2|=indirect(\" a1 \")+1"), vec![vec!["7", "14", "15"]]);
+}
+
+#[test]
+#[should_panic(expected = "Function `indirect` expects a cell reference, got: `xyz`")]
+fn indirect_of_garbage_fails() -> ()
+{
+    eval("=indirect(\"xyz\")");
+}
+
+#[test]
+#[should_panic(expected = "Expected text as `indirect` param")]
+fn indirect_of_a_number_fails() -> ()
+{
+    rows("1|=indirect(5)");
+}
+
+#[test]
+fn fixed_width_of_five() -> ()
+{
+    let options = FormatOptions { width: Width::Fixed(5), ..FormatOptions::default() };
+
+    assert_eq!(Parser::parse_file_with_options(String::from("1|abc\n=A0*100"), &options), "1    |abc  |\n100  |\n");
+}
+
+#[test]
+fn auto_width_fits_each_column() -> ()
+{
+    let options = FormatOptions { width: Width::Auto, ..FormatOptions::default() };
+
+    assert_eq!(Parser::parse_file_with_options(String::from("1|abc\n=A0*1000|x"), &options), "1   |abc|\n1000|x  |\n");
+}
+
+#[test]
+fn alignments_pad_on_their_side() -> ()
+{
+    let format = |alignment: Alignment| -> String
+    {
+        let options = FormatOptions { width: Width::Fixed(5), alignment, ..FormatOptions::default() };
+        Parser::parse_file_with_options(String::from("1|abc"), &options)
+    };
+
+    assert_eq!(format(Alignment::Left), "1    |abc  |\n");
+    assert_eq!(format(Alignment::Right), "    1|  abc|\n");
+    assert_eq!(format(Alignment::Center), "  1  | abc |\n");
+    // Numbers right, text left
+    assert_eq!(format(Alignment::Auto), "    1|abc  |\n");
+}
+
+#[test]
+fn dimensions_before_and_after_evaluating() -> ()
+{
+    let mut sheet = Sheet::new("1|2|=A0+A1\n=sequence(3)| | ");
+    let before = sheet.dimensions();
+    sheet.evaluate().unwrap();
+
+    assert_eq!(before, Dimensions { rows: 2, columns: 3, values: 4, formulas: 2 });
+    assert_eq!(sheet.dimensions(), before);
+}
+
+#[test]
+fn aggregates_of_ranges_skip_blanks() -> ()
+{
+    assert_eq!(rows("1| |3|=sum(A0:A2)|=average(A0:A2)|=max(A0:A2)|=min(A0:A2, 0.5)"), vec![vec!["1", "", "3", "4", "2", "3", "0.5"]]);
+    assert_eq!(rows("1|2\n=sum(A0:A1)|5\n=average(A0:B1)"), vec![vec!["1", "2"], vec!["3", "5"], vec!["2.75"]]);
+}
+
+#[test]
+fn sum_of_a_blank_range_is_zero() -> ()
+{
+    assert_eq!(rows("| |=sum(A0:A1)|=max(A0:A1)|=min(A0:A1)"), vec![vec!["", "", "0", "0", "0"]]);
+}
+
+#[test]
+#[should_panic(expected = "Function `average` has no numbers to average")]
+fn average_of_a_blank_range_fails() -> ()
+{
+    rows("| |=average(A0:A1)");
+}
+
+#[test]
+fn maxifs_and_minifs_of_the_matching_values() -> ()
+{
+    assert_eq!(eval("=maxifs(\"<5\", 1, 7, 4, 2)"), "4");
+    assert_eq!(eval("=minifs(\">1\", 1, 7, 4, 2)"), "2");
+    assert_eq!(eval("=maxifs(2, 1, 2, 2, 3)"), "2");
+    assert_eq!(rows("3|8| |6|=maxifs(\"<7\", A0:A3)|=minifs(\">3\", A0:A3)"), vec![vec!["3", "8", "", "6", "6", "6"]]);
+}
+
+#[test]
+fn maxifs_and_minifs_without_a_match_are_zero() -> ()
+{
+    assert_eq!(eval("=maxifs(\">10\", 1, 7, 4)"), "0");
+    assert_eq!(eval("=minifs(\">10\", 1, 7, 4)"), "0");
+}
+
+#[test]
+fn parser_from_text_is_like_from_tokens() -> ()
+{
+    let from_text = Parser::from("sum(1, 2) * 3").without_folding().parse().to_json();
+    let from_tokens = Parser::new(Tokenizer::new(String::from("sum(1, 2) * 3")).get_tokens()).without_folding().parse().to_json();
+
+    assert_eq!(from_text, from_tokens);
+    assert!(matches!(Parser::from("sum(1, 2) * 3").try_eval_const(), Some(LiteralValue::Float(9.0))));
+}
+
+#[test]
+fn try_eval_const_of_constants_only() -> ()
+{
+    assert!(matches!(Parser::from("2+3*4").try_eval_const(), Some(LiteralValue::Int(14))));
+    assert!(matches!(Parser::from("-(1.5)*2").try_eval_const(), Some(LiteralValue::Float(-3.0))));
+    assert!(Parser::from("A1+1").try_eval_const().is_none());
+    assert!(Parser::from("random()+1").try_eval_const().is_none());
+}
+
+#[test]
+fn constant_subtrees_are_folded() -> ()
+{
+    assert_eq!(Parser::from("2+3*4").parse().to_json(), "{\"type\": \"number\", \"value\": 14}");
+    assert_eq!(Parser::from("2+3*4").without_folding().parse().to_json().matches("binary").count(), 2);
+    assert_eq!(eval("=2+3*4"), "14");
+}
+
+#[test]
+fn random_is_not_folded() -> ()
+{
+    assert!(Parser::from("random()*2").parse().to_json().contains("\"random\""));
+    assert!(Parser::from("randbetween(1, 6)").parse().to_json().contains("\"randbetween\""));
+}
+
+#[test]
+fn a_failing_constant_call_is_kept_for_evaluation() -> ()
+{
+    assert!(Parser::from("find(\"z\", \"abc\")+1").parse().to_json().contains("\"find\""));
+    Sheet::new("=find(\"z\",\"abc\")|=1+2").validate();
+}
+
+#[test]
+fn booleans_in_formulas() -> ()
+{
+    assert_eq!(eval("=if(TRUE, 1, 2)"), "1");
+    assert_eq!(eval("=if(false, 1, 2)"), "2");
+    assert_eq!(eval("=True+1"), "2");
+}
+
+#[test]
+fn or_operator_outside_parentheses_is_one_cell() -> ()
+{
+    assert_eq!(rows("=1||0|=0||0|5"), vec![vec!["1", "0", "5"]]);
+    assert_eq!(rows("1||2"), vec![vec!["1", "", "2"]]);
+    assert_eq!(rows("=0| |5"), vec![vec!["0", "", "5"]]);
+}
+
+#[test]
+fn emit_formulas_writes_the_formulas() -> ()
+{
+    let options = FormatOptions { emit_formulas: true, width: Width::Fixed(1), ..FormatOptions::default() };
+
+    assert_eq!(Parser::parse_file_with_options(String::from("1|=A0+1|text\n=sum(A0:A1)"), &options), "1|=A0+1|text|\n=sum(A0:A1)|\n");
+}
+
+#[test]
+#[should_panic(expected = "Cycle detected")]
+fn emit_formulas_still_evaluates() -> ()
+{
+    let options = FormatOptions { emit_formulas: true, ..FormatOptions::default() };
+
+    Parser::parse_file_with_options(String::from("=A1|=A0"), &options);
+}
+
+#[test]
+fn null_value_for_blank_cells_only() -> ()
+{
+    let options = FormatOptions { null_value: Some(String::from("-")), width: Width::Fixed(1), ..FormatOptions::default() };
+
+    assert_eq!(Parser::parse_file_with_options(String::from("1||=A1*2|0"), &options), "1|-|0|0|\n");
+}
+
+#[test]
+fn power_binds_tighter_than_unary_minus() -> ()
+{
+    assert_eq!(eval("=-2^2"), "-4");
+    assert_eq!(eval("=(-2)^2"), "4");
+    assert_eq!(eval("=2^-1"), "0.5");
+}
+
+#[test]
+fn power_is_right_associative() -> ()
+{
+    assert_eq!(eval("=2^2^3"), "256");
+    assert_eq!(eval("=2*3^2"), "18");
+}
+
+#[test]
+fn writing_to_a_writer_is_like_the_string() -> ()
+{
+    let sheet = "1|2|=A0+A1\n=sum(A0:A2)|text";
+    let options = FormatOptions { alignment: Alignment::Right, ..FormatOptions::default() };
+
+    let mut output = Vec::<u8>::new();
+    Parser::parse_file_to_writer(sheet.to_string(), &options, &mut output).unwrap();
+
+    assert_eq!(String::from_utf8(output).unwrap(), Parser::parse_file_with_options(sheet.to_string(), &options));
+}
+
+#[test]
+fn writing_a_failing_sheet_is_an_error() -> ()
+{
+    let mut output = Vec::<u8>::new();
+    let error = Parser::parse_file_to_writer(String::from("=A1|=A0"), &FormatOptions::default(), &mut output).unwrap_err();
+
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn cells_in_row_major_order() -> ()
+{
+    let mut sheet = Sheet::new("=A1*2|3\n=A0+A1|x");
+    sheet.evaluate().unwrap();
+
+    let cells: Vec<(CellIndex, String)> = sheet.cells().map(|(index, value)| (index, render(value))).collect();
+
+    assert_eq!(cells, vec![
+        (CellIndex::new(0, 0), String::from("6")),
+        (CellIndex::new(0, 1), String::from("3")),
+        (CellIndex::new(1, 0), String::from("9")),
+        (CellIndex::new(1, 1), String::from("x")),
+    ]);
+}
+
+#[test]
+fn empty_input_gives_empty_output() -> ()
+{
+    assert_eq!(Parser::parse_file(String::new()), "");
+    assert_eq!(Parser::parse_file(String::from("  \n\t\n")), "");
+    assert_eq!(Sheet::new("").dimensions(), Dimensions { rows: 0, columns: 0, values: 0, formulas: 0 });
+}
+
+#[test]
+fn substitute_every_occurrence() -> ()
+{
+    assert_eq!(eval("=substitute(\"a-b-c\", \"-\", \"+\")"), "a+b+c");
+    assert_eq!(eval("=substitute(\"héhé\", \"é\", \"e\")"), "hehe");
+    assert_eq!(eval("=substitute(\"abc\", \"\", \"x\")"), "abc");
+}
+
+#[test]
+fn replace_clamps_out_of_range_positions() -> ()
+{
+    assert_eq!(eval("=replace(\"hello\", 2, 3, \"EY\")"), "hEYo");
+    assert_eq!(eval("=replace(\"abc\", 10, 2, \"Z\")"), "abcZ");
+    assert_eq!(eval("=replace(\"abc\", 2, 10, \"Z\")"), "aZ");
+    assert_eq!(eval("=replace(\"héllo\", 2, 1, \"e\")"), "hello");
+}
+
+#[test]
+fn find_is_case_sensitive_and_search_isnt() -> ()
+{
+    assert_eq!(eval("=find(\"b\", \"abcb\")"), "2");
+    assert_eq!(eval("=search(\"B\", \"abc\")"), "2");
+    // Positions are counted in characters, not bytes
+    assert_eq!(eval("=find(\"l\", \"héllo\")"), "3");
+}
+
+#[test]
+#[should_panic(expected = "Function `find` didn't find `B` in `abc`")]
+fn find_without_a_match_fails() -> ()
+{
+    eval("=find(\"B\", \"abc\")");
+}
+
+#[test]
+fn value_of_a_number_text() -> ()
+{
+    assert_eq!(eval("=value(\"3.14\")"), "3.14");
+    assert_eq!(eval("=value(\" 42 \")"), "42");
+}
+
+#[test]
+#[should_panic(expected = "Function `value` can't convert `abc` to a number")]
+fn value_of_other_text_fails() -> ()
+{
+    eval("=value(\"abc\")");
+}
+
+#[test]
+fn text_with_decimals_and_percents() -> ()
+{
+    assert_eq!(eval("=text(3.14159, \"0.00\")"), "3.14");
+    assert_eq!(eval("=text(2, \"0.00\")"), "2.00");
+    assert_eq!(eval("=text(0.256, \"0%\")"), "26%");
+    assert_eq!(eval("=text(0.256, \"0.0%\")"), "25.6%");
+}
+
+#[test]
+fn evaluator_reused_across_sheets() -> ()
+{
+    let mut evaluator = Evaluator::new();
+
+    assert_eq!(values(evaluator.evaluate("1|2|=A0+A1").unwrap()), vec!["1", "2", "3"]);
+    // Nothing is left of the first sheet, its cells are unknown now
+    assert_eq!(values(evaluator.evaluate("10|=A0*2|=A5").unwrap()), vec!["10", "20", "#REF!"]);
+    assert_eq!(values(evaluator.evaluate("=A1|5").unwrap()), vec!["5", "5"]);
+}
+
+#[test]
+fn evaluator_goes_on_after_a_failing_sheet() -> ()
+{
+    let mut evaluator = Evaluator::new();
+
+    assert!(evaluator.evaluate("=A1|=A0").is_err());
+    assert_eq!(values(evaluator.evaluate("=A1|=1").unwrap()), vec!["1", "1"]);
+}
+
+#[test]
+fn iseven_and_isodd_truncate_fractions() -> ()
+{
+    assert_eq!(eval("=iseven(2)"), "1");
+    assert_eq!(eval("=iseven(3)"), "0");
+    assert_eq!(eval("=isodd(3)"), "1");
+    assert_eq!(eval("=isodd(-3)"), "1");
+    assert_eq!(eval("=iseven(2.7)"), "1");
+    assert_eq!(eval("=isodd(2.7)"), "0");
+}
+
+#[test]
+fn color_only_when_enabled() -> ()
+{
+    let format = |color: bool| Parser::parse_file_with_options(String::from("1|=A0+1"), &FormatOptions { color, width: Width::Fixed(1), ..FormatOptions::default() });
+
+    assert!(!format(false).contains('\x1b'));
+    assert_eq!(format(true), format!("1|{}2{}|\n", FORMULA_COLOR, RESET_COLOR));
+}
+
+#[test]
+fn lint_flags_blank_and_undefined_references() -> ()
+{
+    let warnings = Sheet::new("1| |=A0+A1\n=A9*2|=sum(A0:A2)").lint();
+
+    assert_eq!(warnings, vec![
+        LintWarning { cell: CellIndex::new(0, 2), reference: CellIndex::new(0, 1), undefined: false },
+        LintWarning { cell: CellIndex::new(1, 0), reference: CellIndex::new(0, 9), undefined: true },
+    ]);
+    assert_eq!(warnings[1].to_string(), "B0: refers to an undefined cell A9");
+}
+
+#[test]
+fn lint_doesnt_flag_the_cells_a_formula_spills_to() -> ()
+{
+    assert!(Sheet::new("=sequence(3)| \n=A1+A2").lint().is_empty());
+    assert_eq!(Sheet::new("=sequence(3)|5| \n=A2").lint().len(), 1);
+}
+
+#[test]
+fn lint_knows_the_registered_functions() -> ()
+{
+    let mut evaluator = Evaluator::new();
+    evaluator.register_function("one", |_| Ok(LiteralValue::Int(1)));
+
+    let warnings = evaluator.evaluate("1||=one(A1)").unwrap().lint();
+
+    assert_eq!(warnings, vec![LintWarning { cell: CellIndex::new(0, 2), reference: CellIndex::new(0, 1), undefined: false }]);
+}
+
+#[test]
+fn double_equal_and_bang_equal_are_aliases() -> ()
+{
+    assert_eq!(rows("2|1|=if(A1==1,1,0)|=if(A1!=1,1,0)|=if(A0==1,1,0)|=if(A0!=1,1,0)"),
+        vec![vec!["2", "1", "1", "0", "0", "1"]]);
+}
+
+#[test]
+fn profile_times_every_phase() -> ()
+{
+    let mut evaluator = Evaluator::new();
+    assert_eq!(values(evaluator.evaluate("1|=A0+1|=sum(A0:A1)").unwrap()), vec!["1", "2", "3"]);
+
+    let profile = evaluator.profile().to_string();
+    let phases: Vec<_> = profile.lines().map(|line| line.split(':').next().unwrap()).collect();
+
+    assert_eq!(phases, ["load", "tokenize", "parse", "evaluate"]);
+}
+
+#[test]
+fn cell_accessors() -> ()
+{
+    let value = Cell::Value(String::from("42"));
+    let formula = Cell::Expression(String::from("A0+1"));
+
+    assert!(!value.is_formula());
+    assert_eq!(value.as_value(), Some("42"));
+    assert_eq!(value.as_formula(), None);
+
+    assert!(formula.is_formula());
+    assert_eq!(formula.as_value(), None);
+    assert_eq!(formula.clone().as_formula(), Some("A0+1"));
+}
+
+#[test]
+fn floats_keep_f64_precision() -> ()
+{
+    // An `f32` has 24 bits of mantissa, so it would round these to 16777216
+    assert_eq!(eval("=16777216.0+1"), "16777217");
+    assert_eq!(eval("=sum(16777216.5, 1)"), "16777217.5");
+}
+
+#[test]
+fn sequence_spills_into_blank_cells() -> ()
+{
+    assert_eq!(rows("=sequence(3)\n1|=sequence(2)"), vec![vec!["1", "2", "3"], vec!["1", "1", "2"]]);
+}
+
+#[test]
+#[should_panic(expected = "Spill of `A0` is blocked by `A1`")]
+fn sequence_blocked_by_a_cell() -> ()
+{
+    Parser::parse_file(String::from("=sequence(3)|5"));
+}
+
+#[test]
+fn year_month_and_weekday_of_a_serial() -> ()
+{
+    assert_eq!(eval("=date(2024, 3, 15)"), "45366");
+    assert_eq!(eval("=year(45366)"), "2024");
+    assert_eq!(eval("=month(45366)"), "3");
+    // A Friday, weekdays go from 1 (Sunday) to 7 (Saturday)
+    assert_eq!(eval("=weekday(45366)"), "6");
+    assert_eq!(eval("=year(date(1970, 1, 1))"), "1970");
+    assert_eq!(eval("=month(date(2023, 14, 1))"), "2");
+}
+
+#[test]
+fn copy_formula_shifts_relative_references() -> ()
+{
+    let copy = |formula: &str, from: &str, to: &str| CellRef::copy_formula(formula, &CellIndex::from_label(from).unwrap(), &CellIndex::from_label(to).unwrap());
+
+    assert_eq!(copy("A1+B1", "C1", "D1"), "B1+C1");
+    assert_eq!(copy("A1+B1", "C1", "C2"), "A2+B2");
+    assert_eq!(copy("$A1+A$1+$A$1", "C1", "E3"), "$A3+C$1+$A$1");
+    assert_eq!(copy("sum(A0:B2) * 2", "C0", "D0"), "sum(B0:C2) * 2");
+}
+
+#[test]
+#[should_panic(expected = "out of the sheet")]
+fn copy_formula_out_of_the_sheet() -> ()
+{
+    CellRef::copy_formula("A1", &CellIndex::from_label("B1").unwrap(), &CellIndex::from_label("A1").unwrap());
+}
+
+#[test]
+fn countblank_only_counts_blanks() -> ()
+{
+    assert_eq!(rows("1||x|0|=countblank(A0:A3)|=countblank(A1, A9, \"\", \"x\", 0)")[0][4..], ["1", "3"]);
+    assert_eq!(eval("=countblank(1, \"\")"), "1");
+}
+
+#[test]
+fn round_takes_halves_away_from_zero_and_roundeven_to_even() -> ()
+{
+    for (number, round, even) in [("0.5", "1", "0"), ("1.5", "2", "2"), ("2.5", "3", "2"), ("-2.5", "-3", "-2")]
+    {
+        assert_eq!(eval(&format!("=round({})", number)), round);
+        assert_eq!(eval(&format!("=roundeven({})", number)), even);
+    }
+
+    assert_eq!(eval("=round(1.25, 1)"), "1.3");
+    assert_eq!(eval("=roundeven(1.25, 1)"), "1.2");
+}
+
+#[test]
+fn round_to_huge_numbers_of_digits() -> ()
+{
+    assert_eq!(eval("=round(1.5, 400)"), "1.5");
+    assert_eq!(eval("=roundeven(2.5, 400)"), "2.5");
+    assert_eq!(eval("=round(10^300, 100)"), eval("=10^300"));
+    assert_eq!(eval("=round(2.5, -400)"), "0");
+    assert_eq!(eval("=roundeven(-2.5, -400)"), "0");
+}
+
+#[test]
+fn sumsq_and_averagesq() -> ()
+{
+    assert_eq!(eval("=sumsq(3,4)"), "25");
+    assert_eq!(eval("=averagesq(3,4)"), "12.5");
+    // Not `-0`, which an empty sum of floats starts from
+    assert_eq!(rows("||\n=sumsq(A0:A1)")[1], ["0"]);
+}
+
+#[test]
+#[should_panic(expected = "Function `sumsq` expects at least 1 argument, got 0")]
+fn sumsq_without_arguments_fails() -> ()
+{
+    eval("=sumsq()");
+}
+
+#[test]
+fn cycle_error_holds_its_cells() -> ()
+{
+    let mut evaluator = Evaluator::new();
+    evaluator.set_fail_fast(true);
+
+    match evaluator.evaluate("=A1|=A0")
+    {
+        Err(EvalError::Cycle(cells)) =>
+        {
+            assert_eq!(cells, [CellIndex::new(0, 0), CellIndex::new(0, 1)]);
+            assert_eq!(EvalError::Cycle(cells).to_string(), "Cycle detected: A0 -> A1 -> A0");
+        },
+        result => panic!("Expected a cycle, got {:?}", result.map(values)),
+    }
+}
+
+#[test]
+fn force_recalc_draws_again_on_every_reference() -> ()
+{
+    let evaluate = |force_recalc: bool| -> Vec<String>
+    {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_seed(Some(3));
+        evaluator.set_force_recalc(force_recalc);
+
+        values(evaluator.evaluate("=random()|=A0-A0|=A0").unwrap())
+    };
+
+    let cached = evaluate(false);
+    assert_eq!(cached[1], "0");
+    assert_eq!(cached[2], cached[0]);
+
+    // The cell keeps its own value, only its references draw new ones
+    let recalculated = evaluate(true);
+    assert_eq!(recalculated[0], cached[0]);
+    assert_ne!(recalculated[1], "0");
+    assert_ne!(recalculated[2], recalculated[0]);
+}
+
+#[test]
+fn percentile_and_quartile_interpolate() -> ()
+{
+    // Sorted 1, 2, 3, 4, 10: the 0.3 percentile is at rank 1.2, between 2 and 3
+    assert_eq!(eval("=percentile(0.3, 4, 1, 10, 3, 2)"), "2.2");
+    assert_eq!(eval("=percentile(1, 4, 1, 10, 3, 2)"), "10");
+    assert_eq!(eval("=quartile(1, 4, 1, 10, 3, 2)"), "2");
+    assert_eq!(eval("=quartile(2, 1, 2, 3, 4)"), "2.5");
+    assert_eq!(eval("=quartile(4, 1, 2, 3, 4)"), "4");
+}
+
+#[test]
+#[should_panic(expected = "Function `quartile` got `1.5` out of bounds, expected an integer in 0..4")]
+fn quartile_rejects_fractions() -> ()
+{
+    eval("=quartile(1.5, 1, 2, 3)");
+}
+
+#[test]
+#[should_panic(expected = "Function `percentile` got `1.5` out of bounds")]
+fn percentile_rejects_out_of_bounds() -> ()
+{
+    eval("=percentile(1.5, 1, 2, 3)");
+}
+
+#[test]
+fn only_a_leading_equal_starts_a_formula() -> ()
+{
+    assert_eq!(rows("=1+1|a=b|1 = 1"), vec![vec!["2", "a=b", "1 = 1"]]);
+}
+
+#[test]
+#[should_panic(expected = "Cell `A1` starts with `==`")]
+fn double_leading_equal_fails() -> ()
+{
+    Parser::parse_file(String::from("1|==1"));
+}
+
+#[test]
+fn textjoin_of_ranges_with_and_without_blanks() -> ()
+{
+    let row = &rows("a||c|=textjoin(\",\", 1, A0:A2)|=textjoin(\",\", 0, A0:A2)|=textjoin(\"-\", 1, A0:A2, 5)")[0];
+
+    assert_eq!(row[3..], ["a,c", "a,,c", "a-c-5"]);
+    assert_eq!(eval("=concatenate(\"a\", 1, \"b\")"), "a1b");
+}
+
+#[test]
+fn validate_never_runs_a_failing_function() -> ()
+{
+    let sheet = Sheet::new("=fact(1000000000)|=1/0|=A0+1");
+    sheet.validate();
+
+    assert_eq!(sheet.dependencies(&CellIndex::new(0, 2)), [CellIndex::new(0, 0)]);
+    // Folding leaves the failing call to the evaluation, where it can be caught
+    assert_eq!(eval("=iserror(fact(1000000000))"), "1");
+}
+
+#[test]
+#[should_panic(expected = "missing 1 `)`")]
+fn validate_fails_on_syntax_errors() -> ()
+{
+    Sheet::new("=1|=(2").validate();
+}
+
+#[test]
+fn rounding_to_multiples_of_a_quarter() -> ()
+{
+    assert_eq!(eval("=mround(1.3, 0.25)"), "1.25");
+    assert_eq!(eval("=mround(1.4, 0.25)"), "1.5");
+    assert_eq!(eval("=floorm(1.49, 0.25)"), "1.25");
+    assert_eq!(eval("=ceilm(1.26, 0.25)"), "1.5");
+    assert_eq!(eval("=ceilm(1.5, 0.25)"), "1.5");
+}
+
+#[test]
+#[should_panic(expected = "Function `mround` can't round to a multiple of 0")]
+fn rounding_to_multiples_of_zero_fails() -> ()
+{
+    eval("=mround(1.3, 0)");
+}
+
+#[test]
+fn lone_carriage_returns_end_lines() -> ()
+{
+    assert_eq!(rows("1\r=A0+1\r\n=B0*2\n"), vec![vec!["1"], vec!["2"], vec!["4"], vec![""]]);
+}
+
+#[test]
+fn header_rows_keep_their_place() -> ()
+{
+    let mut evaluator = Evaluator::new();
+    evaluator.set_header_rows(2);
+
+    // The formula of the header is a value, the first data row is still `C`
+    assert_eq!(values(evaluator.evaluate("Title|=x\nUnits\n1|=C0*2|=A1").unwrap()), ["Title", "=x", "Units", "1", "2", "=x"]);
+}
+
+#[test]
+fn references_of_a_formula() -> ()
+{
+    let references = |formula: &str| Cell::Expression(formula.to_string()).references(&CellIndex::new(0, 0), &[]);
+
+    assert_eq!(references("A1 + sum(B1, C2)"), [CellIndex::new(0, 1), CellIndex::new(1, 1), CellIndex::new(2, 2)]);
+    assert_eq!(references("A1 * A1 - -B0"), [CellIndex::new(0, 1), CellIndex::new(0, 1), CellIndex::new(1, 0)]);
+    assert_eq!(references("1 + 2"), []);
+    assert_eq!(Cell::Value(String::from("A1")).references(&CellIndex::new(0, 0), &[]), []);
+}
+
+#[test]
+fn greatest_and_least_match_max_and_min() -> ()
+{
+    assert_eq!(eval("=greatest(3, -1.5, 7, 2)"), eval("=max(3, -1.5, 7, 2)"));
+    assert_eq!(eval("=least(3, -1.5, 7, 2)"), eval("=min(3, -1.5, 7, 2)"));
+    assert_eq!(rows("1|5|=greatest(A0:A1)|=least(A0:A1)"), rows("1|5|=max(A0:A1)|=min(A0:A1)"));
+}
+
+#[test]
+fn clamp_between_any_number_of_bounds() -> ()
+{
+    assert_eq!(eval("=clamp(15, 0, 10)"), "10");
+    assert_eq!(eval("=clamp(-5, 10, 0)"), "0");
+    assert_eq!(eval("=clamp(4, 0, 10)"), "4");
+    assert_eq!(eval("=clamp(12, 3, 8, 5, 1)"), "8");
+    assert_eq!(rows("2|9|5|=clamp(20, A0:A2)|=clamp(0, A0:A2)"), vec![vec!["2", "9", "5", "9", "2"]]);
+}
+
+#[test]
+#[should_panic(expected = "Function `clamp` has no bounds")]
+fn clamp_of_a_blank_range_fails() -> ()
+{
+    rows("| |=clamp(3, A0:A1)");
+}
+
+#[test]
+#[should_panic(expected = "Function `clamp` got `NaN` as a bound, expected a number")]
+fn clamp_to_a_nan_bound_fails() -> ()
+{
+    eval("=clamp(1, 0, 0/0)");
+}
+
+#[test]
+fn equality_tolerates_float_rounding() -> ()
+{
+    assert_eq!(rows("=if(0.1+0.2 = 0.3, 1, 0)|=if(0.1+0.2 <> 0.3, 1, 0)|=0.1+0.2 > 0.3"), vec![vec!["1", "0", "1"]]);
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_epsilon(0.0);
+
+    assert_eq!(values(evaluator.evaluate("=if(0.1+0.2 = 0.3, 1, 0)|=1 = 1.0").unwrap()), ["0", "1"]);
+}
+
+#[test]
+fn hypot_and_norm() -> ()
+{
+    assert_eq!(eval("=hypot(3,4)"), "5");
+    assert_eq!(eval("=hypot(-3,4)"), "5");
+    assert_eq!(eval("=norm(1,2,2)"), "3");
+    assert_eq!(eval("=norm(-3)"), "3");
+}
+
+#[test]
+#[should_panic(expected = "Function `norm` expects at least 1 argument, got 0")]
+fn norm_without_arguments_fails() -> ()
+{
+    eval("=norm()");
+}
+
+#[test]
+fn references_to_other_sheets() -> ()
+{
+    let output = rows("1|2|=Prices!A0*10|=prices!A1\n=== Prices ===\n5|=A0+1");
+
+    assert_eq!(output[0], ["1", "2", "50", "6"]);
+    assert_eq!(output[1], ["=== Prices ==="]);
+    assert_eq!(output[2], ["5", "6"]);
+}
+
+#[test]
+#[should_panic(expected = "Unknown sheet `Nope`")]
+fn reference_to_an_unknown_sheet_fails() -> ()
+{
+    Parser::parse_file(String::from("1|=Nope!A0"));
+}
+
+#[test]
+fn argument_counts_have_uniform_messages() -> ()
+{
+    for (formula, message) in [("=if(1,2)", "Function `if` expects 3 arguments, got 2"),
+                                ("=hypot(1)", "Function `hypot` expects 2 arguments, got 1"),
+                                ("=round(1,2,3)", "Function `round` expects 1 to 2 arguments, got 3"),
+                                ("=sumsq()", "Function `sumsq` expects at least 1 argument, got 0")]
+    {
+        let error = catch_panic(|| eval(formula)).unwrap_err();
+        assert!(error.contains(message), "`{}` failed with `{}`", formula, error);
+    }
+}
+
+#[test]
+fn roundsig_keeps_significant_figures() -> ()
+{
+    assert_eq!(eval("=roundsig(12345, 2)"), "12000");
+    assert_eq!(eval("=roundsig(0.012345, 2)"), "0.012");
+    assert_eq!(eval("=roundsig(-12345, 3)"), "-12300");
+    assert_eq!(eval("=roundsig(0, 2)"), "0");
+}
+
+#[test]
+#[should_panic(expected = "Function `roundsig` keeps at least 1 significant figure, got `0`")]
+fn roundsig_to_no_figures_fails() -> ()
+{
+    eval("=roundsig(12345, 0)");
+}
+
+#[test]
+#[should_panic(expected = "Cell `A1` is missing 2 `)`: `=(1+(2`")]
+fn missing_closing_parenthese() -> ()
+{
+    Parser::parse_file(String::from("1|=(1+(2"));
+}
+
+#[test]
+#[should_panic(expected = "Cell `A0` has a `)` without a matching `(`: `=1+2)`")]
+fn extra_closing_parenthese() -> ()
+{
+    Parser::parse_file(String::from("=1+2)"));
+}
+
+#[test]
+fn format_grid_with_different_options() -> ()
+{
+    let mut cells = Table::new();
+    cells.insert(CellIndex::new(0, 0), Cell::Value(String::from("1")));
+    cells.insert(CellIndex::new(0, 1), Cell::Expression(String::from("A0+1")));
+    cells.insert(CellIndex::new(1, 0), Cell::Value(String::from("abc")));
+    cells.insert(CellIndex::new(1, 1), Cell::Value(String::new()));
+
+    assert_eq!(format_grid(&cells, &FormatOptions::default()), "1         |=A0+1     |\nabc       |          |\n");
+
+    let options = FormatOptions { width: Width::Fixed(4), alignment: Alignment::Right, null_value: Some(String::from("-")), ..FormatOptions::default() };
+    assert_eq!(format_grid(&cells, &options), "   1|=A0+1|\n abc|   -|\n");
+}
+
+#[test]
+fn relative_references_to_the_left_and_above() -> ()
+{
+    assert_eq!(rows("1|=RC[-1]+1|=RC[-2]+RC[-1]\n=R[-1]C*10|=R[-1]C[1]"), vec![vec!["1", "2", "3"], vec!["10", "3"]]);
+}
+
+#[test]
+#[should_panic(expected = "Relative reference `R[-1]C` from `A0` points before the first row or column")]
+fn relative_reference_before_the_first_row_fails() -> ()
+{
+    Parser::parse_file(String::from("=R[-1]C"));
+}
+
+#[test]
+fn diagnose_parse_failures_cycles_and_failing_calls() -> ()
+{
+    let diagnostics = Evaluator::new().diagnose("=1)|=A2|=A1\n=find(\"z\", \"abc\")|1");
+    let kinds: Vec<(String, DiagnosticKind)> = diagnostics.into_iter().map(|diagnostic| (diagnostic.cell.unwrap().to_string(), diagnostic.kind)).collect();
+
+    assert_eq!(kinds, vec![
+        (String::from("A0"), DiagnosticKind::Parse),
+        (String::from("A1"), DiagnosticKind::Cycle),
+        (String::from("A2"), DiagnosticKind::Cycle),
+        (String::from("B0"), DiagnosticKind::Eval),
+    ]);
+}
+
+#[test]
+fn copysign_and_nearbyint() -> ()
+{
+    assert_eq!(eval("=copysign(3, -0.5)"), "-3");
+    assert_eq!(eval("=copysign(-3, 2)"), "3");
+    assert_eq!(eval("=copysign(-3, -2)"), "-3");
+    assert_eq!(eval("=nearbyint(2.5)"), "2");
+    assert_eq!(eval("=nearbyint(3.5)"), "4");
+    assert_eq!(eval("=nearbyint(-1.5)"), "-2");
+    assert_eq!(eval("=nearbyint(2.4)"), "2");
+}
+
+#[test]
+#[should_panic(expected = "Function `copysign` expects 2 arguments, got 1")]
+fn copysign_needs_two_arguments() -> ()
+{
+    eval("=copysign(3)");
+}
+
+#[test]
+fn tabs_separate_columns_with_tab_width() -> ()
+{
+    let mut evaluator = Evaluator::new();
+    evaluator.set_tab_width(true);
+
+    assert_eq!(values(evaluator.evaluate("1\t2|3\n=sum(A0:A2)\t=if(1,\t4,\t5)").unwrap()), vec!["1", "2", "3", "6", "4"]);
+
+    // Without it a tab is only a space within formulas
+    assert_eq!(values(Evaluator::new().evaluate("=1\t+\t2|3").unwrap()), vec!["3", "3"]);
+}
+
+#[test]
+fn with_formulas_writes_the_formula_after_the_value() -> ()
+{
+    let options = FormatOptions { with_formulas: true, width: Width::Fixed(1), ..FormatOptions::default() };
+
+    assert_eq!(Parser::parse_file_with_options(String::from("1|2|=A0 + A1"), &options), "1|2|3 (=A0 + A1)|\n");
+}
+
+#[test]
+fn randarray_is_reproducible_with_a_seed() -> ()
+{
+    let evaluate = |seed: u64| -> Vec<String>
+    {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_seed(Some(seed));
+
+        values(evaluator.evaluate("=randarray(4)").unwrap())
+    };
+
+    let first = evaluate(42);
+
+    assert_eq!(first.len(), 4);
+    assert_eq!(first, evaluate(42));
+    assert_ne!(first, evaluate(43));
+    assert!(first.iter().all(|value| (0.0..1.0).contains(&value.parse::<f64>().unwrap())));
+}
+
+#[test]
+#[should_panic(expected = "randarray")]
+fn randarray_of_nothing_fails() -> ()
+{
+    Parser::parse_file(String::from("=randarray(0)"));
+}
+
+#[test]
+fn eval_expression_without_a_sheet() -> ()
+{
+    assert_eq!(eval("2+3*4"), "14");
+    assert_eq!(eval("=sum(1,2,3)"), "6");
+    assert!(matches!(eval_expression("=A1"), Err(EvalError::NoSheet(index)) if index == CellIndex::new(0, 1)));
+}
+
+#[test]
+fn function_names_ignore_case() -> ()
+{
+    assert_eq!(eval("=SUM(1,2)"), "3");
+    assert_eq!(eval("=Max(1,2) + mIn(3,4)"), "5");
+    assert_eq!(rows("1|=Sum(A0,1)|=IF(A1=2, 10, 20)")[0], ["1", "2", "10"]);
+}
+
+#[test]
+fn n_coerces_texts_and_blanks_to_zero() -> ()
+{
+    assert_eq!(rows("5|abc||=n(A0)|=n(A1)|=n(A2)|=n(\"12\")|=n(2.5)")[0][3..], ["5", "0", "0", "0", "2.5"]);
+}
+
+#[test]
+#[should_panic(expected = "Invalid cell reference letters: ``")]
+fn text_to_number_of_nothing_fails() -> ()
+{
+    CellRef::text_to_number(String::new());
+}
+
+#[test]
+#[should_panic(expected = "Invalid cell reference letters: `12`")]
+fn text_to_number_of_digits_fails() -> ()
+{
+    CellRef::text_to_number(String::from("12"));
+}
+
+#[test]
+#[should_panic(expected = "are out of range")]
+fn text_to_number_overflow_fails() -> ()
+{
+    CellRef::text_to_number("Z".repeat(20));
+}
+
+#[test]
+fn rows_and_columns_of_the_sheet() -> ()
+{
+    // The full rectangle, blank cells and shorter rows included
+    assert_eq!(rows("1||3\n=rows()|=columns()\n\n=rows()*columns()")[1], ["4", "3"]);
+    assert_eq!(rows("=rows()|=columns()\n=== Other ===\n1\n2\n3")[0], ["1", "2"]);
+}
+
+#[test]
+fn thousands_separator_for_large_and_small_numbers() -> ()
+{
+    let options = FormatOptions { thousands_separator: Some(','), width: Width::Fixed(1), ..FormatOptions::default() };
+
+    assert_eq!(Parser::parse_file_with_options(String::from("1234567|=A0*-1|999|0.5|12345.678|abc1234"), &options),
+        "1,234,567|-1,234,567|999|0.5|12,345.678|abc1234|\n");
+
+    // Not with `emit_formulas`, the output can still be read back
+    let options = FormatOptions { emit_formulas: true, ..options };
+    assert_eq!(Parser::parse_file_with_options(String::from("1234567|=A0*2"), &options), "1234567|=A0*2|\n");
+}
+
+#[test]
+fn first_and_last_skip_blanks() -> ()
+{
+    assert_eq!(rows("||=first(A0, A1, 3, 4)|=first(A0, \"\", \"x\")|=last(1, 2, A0, A1)|=last(A0, 5)")[0][2..], ["3", "x", "2", "5"]);
+}
+
+#[test]
+#[should_panic(expected = "Function `first` only got blank arguments")]
+fn first_of_blanks_fails() -> ()
+{
+    Parser::parse_file(String::from("||=first(A0, A1, \"\")"));
+}
+
+#[test]
+fn weightedaverage_of_pairs() -> ()
+{
+    assert_eq!(eval("=weightedaverage(1, 1, 3, 3)"), "2.5");
+    assert_eq!(eval("=weightedaverage(80, 0.5, 90, 0.25, 100, 0.25)"), "87.5");
+}
+
+#[test]
+#[should_panic(expected = "Function `weightedaverage` got a total weight of 0")]
+fn weightedaverage_of_no_weight_fails() -> ()
+{
+    eval("=weightedaverage(1, 0, 2, 0)");
+}
+
+#[test]
+#[should_panic(expected = "Function `weightedaverage` expects pairs of a value and its weight, got 3 arguments")]
+fn weightedaverage_of_an_odd_count_fails() -> ()
+{
+    eval("=weightedaverage(1, 2, 3)");
+}
+
+#[test]
+fn degrees_and_radians() -> ()
+{
+    let number = |formula: &str| eval(formula).parse::<f64>().unwrap();
+
+    assert_eq!(number("=degrees(pi())"), 180.0);
+    assert!((number("=radians(180)") - std::f64::consts::PI).abs() < 1e-12);
+    assert!((number("=degrees(radians(37.5))") - 37.5).abs() < 1e-12);
+}
+
+#[test]
+fn inverse_trigonometry() -> ()
+{
+    let number = |formula: &str| eval(formula).parse::<f64>().unwrap();
+
+    assert!((number("=asin(1)") - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+    assert_eq!(number("=acos(1)"), 0.0);
+    assert!((number("=atan(1)") - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+    assert!((number("=atan2(1, -1)") - 3.0 * std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+}
+
+#[test]
+#[should_panic(expected = "Function `asin` got `2`, expected a number from -1 to 1")]
+fn asin_out_of_its_domain_fails() -> ()
+{
+    eval("=asin(2)");
+}
+
+#[test]
+#[should_panic(expected = "Function `acos` got `-1.5`, expected a number from -1 to 1")]
+fn acos_out_of_its_domain_fails() -> ()
+{
+    eval("=acos(-1.5)");
+}
+
+#[test]
+fn fact_combin_and_permut() -> ()
+{
+    assert_eq!(eval("=fact(5)"), "120");
+    assert_eq!(eval("=fact(0)"), "1");
+    assert_eq!(eval("=combin(5,2)"), "10");
+    assert_eq!(eval("=permut(5,2)"), "20");
+}
+
+#[test]
+#[should_panic(expected = "too large")]
+fn fact_of_a_huge_number_fails_right_away() -> ()
+{
+    eval("=fact(1000000000)");
+}
+
+#[test]
+#[should_panic(expected = "too large")]
+fn combin_of_huge_numbers_fails_right_away() -> ()
+{
+    eval("=combin(4000000000,2000000000)");
+}
+
+#[test]
+#[should_panic(expected = "too large")]
+fn permut_of_huge_numbers_fails_right_away() -> ()
+{
+    eval("=permut(4000000000,2000000000)");
+}
+
+#[test]
+fn registered_function_in_a_formula() -> ()
+{
+    let mut evaluator = Evaluator::new();
+    evaluator.register_function("double", |args| match args
+    {
+        [LiteralValue::Float(f)] => Ok(LiteralValue::Float(f * 2.0)),
+        [LiteralValue::Int(i)] => Ok(LiteralValue::Int(i * 2)),
+        _ => panic!("Function `double` expects a number"),
+    });
+
+    assert_eq!(values(evaluator.evaluate("=double(2)|=DOUBLE(A0)+0.5").unwrap()), vec!["4", "8.5"]);
+
+    // Referring to a formula checks whether it's random, which has to know the registered functions
+    evaluator.set_force_recalc(true);
+    assert_eq!(values(evaluator.evaluate("=double(2)|=A0*3").unwrap()), vec!["4", "12"]);
+
+    // Parsing without evaluating knows them too
+    let sheet = evaluator.evaluate("1|=double(A0)").unwrap();
+    sheet.validate();
+    assert!(sheet.syntax_trees()[0].json.as_ref().unwrap().contains("\"function\""));
+
+    let functions = [String::from("double")];
+    assert_eq!(Cell::Expression(String::from("double(A0)")).references(&CellIndex::new(0, 1), &functions), [CellIndex::new(0, 0)]);
+}
+
+#[test]
+fn every_failure_is_collected_unless_failing_fast() -> ()
+{
+    let sheet = "=fact(-1)|1|=A1+1\n=B1|=B0";
+
+    match Evaluator::new().evaluate(sheet)
+    {
+        Err(EvalError::Failures(diagnostics)) =>
+        {
+            let kinds: Vec<_> = diagnostics.iter().map(|diagnostic| (diagnostic.cell.clone().unwrap().to_string(), diagnostic.kind)).collect();
+
+            assert_eq!(kinds, [(String::from("A0"), DiagnosticKind::Eval), (String::from("B0"), DiagnosticKind::Cycle), (String::from("B1"), DiagnosticKind::Cycle)]);
+        },
+        result => panic!("Expected every failure, got {:?}", result.map(values)),
+    }
+
+    let mut evaluator = Evaluator::new();
+    evaluator.set_fail_fast(true);
+
+    // Only the first failure, the cycle isn't reached
+    assert!(catch_panic(move || evaluator.evaluate(sheet).is_ok()).unwrap_err().contains("fact"));
+}
+
+#[test]
+fn wide_sheet_is_written_unchanged() -> ()
+{
+    const COLUMNS: usize = 5000;
+
+    let row = (0..COLUMNS).map(|column| if column == 0 { String::from("0") } else { format!("=A0+{}", column) }).collect::<Vec<String>>().join("|");
+    let sheet = format!("{}\n{}", row, row.replace("A0", "B0"));
+
+    let expected: Vec<String> = (0..COLUMNS).map(|column| column.to_string()).collect();
+    assert_eq!(rows(&sheet), vec![expected.clone(), expected]);
+}
+
+#[test]
+fn labels_of_many_rows_round_trip() -> ()
+{
+    for row in 0..20000
+    {
+        assert_eq!(CellRef::text_to_number(CellRef::number_to_text(row)), row);
+    }
+}
+
+#[test]
+fn trim_blank_rows_only_when_asked() -> ()
+{
+    let sheet = String::from("1|2\n||\n3\n \n=A0+C0");
+    let format = |trim_blank_rows: bool| Parser::parse_file_with_options(sheet.clone(), &FormatOptions { trim_blank_rows, width: Width::Fixed(1), ..FormatOptions::default() });
+
+    assert_eq!(format(false), "1|2|\n | | |\n3|\n |\n4|\n");
+    assert_eq!(format(true), "1|2|\n3|\n4|\n");
+}
+
+#[test]
+fn runningsum_spills_the_totals() -> ()
+{
+    assert_eq!(rows("=runningsum(1, 2, 3)\n1|2||4|=runningsum(B0:B3)"), vec![vec!["1", "3", "6"], vec!["1", "2", "", "4", "1", "3", "7"]]);
+}
+
+#[test]
+#[should_panic(expected = "Spill of `A0` is blocked by `A1`")]
+fn runningsum_blocked_by_a_cell() -> ()
+{
+    Parser::parse_file(String::from("=runningsum(1, 2, 3)|x"));
+}
+
+#[test]
+fn spills_go_first_whether_failing_fast_or_not() -> ()
+{
+    // `A0` only calls `sequence`, it doesn't spill, so `B0` fills `B1` before `A0` reads it
+    let sheet = "=sum(sequence(2))+B1\n=sequence(3)";
+
+    for fail_fast in [false, true]
+    {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_fail_fast(fail_fast);
+
+        assert_eq!(values(evaluator.evaluate(sheet).unwrap()), ["5", "1", "2", "3"]);
+    }
+}
+
+#[test]
+fn iserror_and_isna_on_a_failure_and_a_valid_value() -> ()
+{
+    assert_eq!(eval("=iserror(1/0)"), "1");
+    assert_eq!(eval("=iserror(1+1)"), "0");
+    assert_eq!(eval("=isna(1/0)"), "0");
+    assert_eq!(eval("=isna(1+1)"), "0");
+    assert_eq!(eval("=iserror(find(\"z\", \"abc\"))"), "1");
+}
+
+#[test]
+fn isref_only_takes_a_written_reference() -> ()
+{
+    assert_eq!(rows("1|=isref(A0)|=isref(1)"), vec![vec!["1", "1", "0"]]);
+}
+
+#[test]
+fn catch_panic_from_several_threads() -> ()
+{
+    let threads: Vec<_> = (0..8)
+        .map(|i| std::thread::spawn(move || (0..50).all(|_| catch_panic(|| panic!("failure {}", i)) == Err(format!("failure {}", i)))))
+        .collect();
+
+    assert!(threads.into_iter().all(|thread| thread.join().unwrap()));
+}
+
+#[test]
+fn round_output_only_rounds_what_is_written() -> ()
+{
+    let options = FormatOptions { round_output: Some(2), width: Width::Fixed(1), ..FormatOptions::default() };
+
+    // `A1` is written rounded, `A2` still gets its full value
+    assert_eq!(Parser::parse_file_with_options(String::from("2|=A0/3|=A1*3|7.5|abc"), &options), "2|0.67|2|7.5|abc|\n");
+}
+
+#[test]
+fn joinformat_writes_each_number_with_the_format() -> ()
+{
+    let row = &rows("5|12.345||-1|=joinformat(\",\", \"0.00\", A0:A3)|=joinformat(\"; \", \"0%\", 0.5, \"x\")")[0];
+
+    assert_eq!(row[4..], ["5.00,12.35,-1.00", "50%; x"]);
+}
+
+#[test]
+fn syntax_trees_as_json() -> ()
+{
+    let trees = Sheet::new("=A1 + 2*3|=(1").syntax_trees();
+
+    assert_eq!(trees[0].json, Ok(String::from(concat!(
+        "{\"type\": \"binary\", \"operator\": \"+\", ",
+        "\"left\": {\"type\": \"cell\", \"ref\": \"A1\", \"cell\": \"A1\"}, ",
+        "\"right\": {\"type\": \"binary\", \"operator\": \"*\", \"left\": {\"type\": \"number\", \"value\": 2}, \"right\": {\"type\": \"number\", \"value\": 3}}}"))));
+    assert_eq!(trees[1].cell, CellIndex::new(0, 1));
+    assert_eq!(trees[1].json, Err(String::from("Cell `A1` is missing 1 `)`: `=(1`")));
+}
+
+#[test]
+fn reference_to_an_unknown_cell_is_ref_error() -> ()
+{
+    assert_eq!(rows("1|=A9|=A1+1|=A0*2"), vec![vec!["1", "#REF!", "#REF!", "2"]]);
+    assert_eq!(rows("1|=indirect(\"A9\")|=offset(A0, -1, 0)"), vec![vec!["1", "#REF!", "#REF!"]]);
+}
+
+#[test]
+fn isna_of_ref_error_doesnt_depend_on_evaluation_order() -> ()
+{
+    assert_eq!(rows("=isna(A1)|=A9|=isna(A1)|=iserror(A1)"), vec![vec!["1", "#REF!", "1", "1"]]);
+    assert_eq!(rows("=isna(indirect(\"A9\"))"), vec![vec!["1"]]);
+}
+
+#[test]
+fn average_of_huge_numbers_does_not_overflow() -> ()
+{
+    // Their sum would be infinite, their mean isn't
+    let mut evaluator = Evaluator::new();
+    assert_eq!(values(evaluator.evaluate("1e308|1e308|1e308|=average(A0:A2)").unwrap())[3].parse::<f64>().unwrap(), 1e308);
+    assert_eq!(eval("=average(1, 2, 3, 4)"), "2.5");
+    assert_eq!(eval("=averagesq(3, 4)"), "12.5");
+}
+
+#[test]
+fn sheet_name_written_once_above_the_grid() -> ()
+{
+    let options = FormatOptions { sheet_name: Some(String::from("Totals")), width: Width::Fixed(1), ..FormatOptions::default() };
+
+    assert_eq!(Parser::parse_file_with_options(String::from("1|2\n=A0+A1"), &options), "=== Totals ===\n1|2|\n3|\n");
+    // The named sheets keep their own name
+    assert_eq!(Parser::parse_file_with_options(String::from("1\n=== Other ===\n2"), &options), "=== Totals ===\n1|\n=== Other ===\n2|\n");
+}
+
+#[test]
+fn coalesce_takes_the_default_for_blanks() -> ()
+{
+    assert_eq!(rows("5|||=coalesce(A0, 9)|=coalesce(A1, 9)|=coalesce(A2, \"none\")|=coalesce(A9, 1)|=coalesce(0, 1)")[0][3..], ["5", "9", "none", "1", "0"]);
+}
+
+#[test]
+fn columns_project_non_adjacent_columns() -> ()
+{
+    let mut cells = Table::new();
+
+    for (index, value) in [((0, 0), "1"), ((0, 1), "2"), ((0, 2), "3"), ((1, 0), "4"), ((1, 1), "5")]
+    {
+        cells.insert(CellIndex::new(index.0, index.1), Cell::Value(value.to_string()));
+    }
+
+    let options = FormatOptions { columns: Some(vec![2, 0]), width: Width::Fixed(2), ..FormatOptions::default() };
+
+    assert_eq!(format_grid(&cells, &options), "3 |1 |\n  |4 |\n");
+}
+
+#[test]
+fn sumif_and_countif_with_criteria() -> ()
+{
+    let row = &rows("5|12|-1|20|0|=sumif(A0:A4, \">10\")|=countif(A0:A4, \"<=0\")|=sumif(A0:A4, \"<>5\")|=countif(A0:A4, 5)")[0];
+
+    assert_eq!(row[5..], ["32", "2", "31", "1"]);
+}
+
+#[test]
+#[should_panic(expected = "Malformed criterion: `=>5`")]
+fn malformed_criterion_fails() -> ()
+{
+    Parser::parse_file(String::from("1|2|=sumif(A0:A1, \"=>5\")"));
+}
+
+#[test]
+fn seed_per_cell_gives_each_cell_its_own_values() -> ()
+{
+    let evaluate = |sheet: &str| -> Vec<String>
+    {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_seed(Some(7));
+        evaluator.set_seed_per_cell(true);
+
+        values(evaluator.evaluate(sheet).unwrap())
+    };
+
+    let first = evaluate("=random()|=random()\n=randbetween(1, 1000000)");
+
+    assert_eq!(first, evaluate("=random()|=random()\n=randbetween(1, 1000000)"));
+    assert_ne!(first[0], first[1]);
+
+    // The other cells don't change the values of a cell
+    assert_eq!(evaluate("=random()|=random()|=random()\n=randbetween(1, 1000000)")[3], first[2]);
+}
+
+#[test]
+fn integers_stay_exact() -> ()
+{
+    assert_eq!(eval("=1000000+1"), "1000001");
+    // Not representable as a float, `9007199254740992.0 + 1` is still `9007199254740992`
+    assert_eq!(eval("=9007199254740993"), "9007199254740993");
+    assert_eq!(eval("=9007199254740993 + 2 - 1"), "9007199254740994");
+    assert_eq!(eval("=9007199254740993 * 2 / 2"), "9007199254740993");
+    assert_eq!(rows("9007199254740993|=A0+0|=A1-A0")[0], ["9007199254740993", "9007199254740993", "0"]);
+    assert_eq!(eval("=7/2"), "3.5");
+}
+
+#[test]
+fn explain_dependencies_of_a_chain() -> ()
+{
+    let sheet = Sheet::new("5|=A0*2|=A1+1|=A2+A0");
+
+    assert_eq!(sheet.explain_dependencies(&CellIndex::new(0, 3)), "A3: =A2+A0\n  A2: =A1+1\n    A1: =A0*2\n      A0: 5\n  A0: 5");
+}
+
+#[test]
+fn dependencies_dont_evaluate_failing_calls() -> ()
+{
+    let sheet = Sheet::new("5|=find(\"z\",\"abc\")+A0");
+
+    assert_eq!(sheet.dependencies(&CellIndex::new(0, 1)), vec![CellIndex::new(0, 0)]);
+    assert_eq!(sheet.explain_dependencies(&CellIndex::new(0, 1)), "A1: =find(\"z\",\"abc\")+A0\n  A0: 5");
+    assert_eq!(Cell::Expression(String::from("find(\"z\",\"abc\")+A0")).references(&CellIndex::new(0, 1), &[]), vec![CellIndex::new(0, 0)]);
+}
+
+#[test]
+fn explain_dependencies_of_a_registered_function() -> ()
+{
+    let mut evaluator = Evaluator::new();
+    evaluator.register_function("double", |args| match args
+    {
+        [LiteralValue::Int(i)] => Ok(LiteralValue::Int(i * 2)),
+        _ => panic!("Function `double` expects an integer"),
+    });
+
+    let sheet = evaluator.evaluate("1|=double(A0)").unwrap();
+
+    assert_eq!(sheet.dependencies(&CellIndex::new(0, 1)), [CellIndex::new(0, 0)]);
+    assert_eq!(sheet.explain_dependencies(&CellIndex::new(0, 1)), "A1: =double(A0)\n  A0: 1");
+}
+
+#[test]
+fn cycles_through_function_arguments() -> ()
+{
+    let cycle = |sheet: &str| -> Vec<String>
+    {
+        let mut evaluator = Evaluator::new();
+        evaluator.set_fail_fast(true);
+
+        match evaluator.evaluate(sheet)
+        {
+            Err(EvalError::Cycle(cells)) => cells.iter().map(CellIndex::to_string).collect(),
+            result => panic!("Expected a cycle in `{}`, got {:?}", sheet, result.map(values)),
+        }
+    };
+
+    assert_eq!(cycle("=sum(A1)|=A0"), ["A0", "A1"]);
+    assert_eq!(cycle("1|=sum(A0:A2)|5"), ["A1"]);
+    assert_eq!(cycle("=if(1, max(B0, 2), 0)\n=A0"), ["A0", "B0"]);
+}
+
+#[test]
+fn column_formats_render_each_column_with_its_format() -> ()
+{
+    let mut cells = Table::new();
+
+    for (column, value) in ["1", "2", "0.5", "0.5"].into_iter().enumerate()
+    {
+        cells.insert(CellIndex::new(0, column), Cell::Value(value.to_string()));
+    }
+
+    let mut options = FormatOptions { width: Width::Fixed(1), ..FormatOptions::default() };
+    options.column_formats.insert(1, NumberFormat::parse("$0.00").unwrap());
+    options.column_formats.insert(2, NumberFormat::parse("0%").unwrap());
+
+    assert_eq!(format_grid(&cells, &options), "1|$2.00|50%|0.5|\n");
+}
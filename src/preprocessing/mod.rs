@@ -0,0 +1,64 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+mod tests;
+
+const INCLUDE_DIRECTIVE: &str = "#include";
+
+pub fn expand_includes(path: &Path) -> io::Result<String>
+{
+    expand(path, &mut vec![])
+}
+
+// Lines may end with `\n`, `\r\n` or a lone `\r` (old Mac files)
+pub fn split_lines(content: &str) -> Vec<&str>
+{
+    content.split("\r\n").flat_map(|line| line.split(['\n', '\r'])).collect()
+}
+
+fn expand(path: &Path, including: &mut Vec<PathBuf>) -> io::Result<String>
+{
+    let content = fs::read_to_string(path)
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("File `{}` not found", path.display())))?;
+
+    let canonical = fs::canonicalize(path)?;
+
+    if including.contains(&canonical)
+    {
+        let mut cycle = including.iter()
+            .skip_while(|x| **x != canonical)
+            .map(|x| x.display().to_string())
+            .collect::<Vec<String>>();
+        cycle.push(canonical.display().to_string());
+
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Include cycle detected, {:?}", cycle.join(" -> "))));
+    }
+
+    including.push(canonical);
+
+    let mut lines = Vec::<String>::new();
+
+    for line in split_lines(&content)
+    {
+        match line.trim().strip_prefix(INCLUDE_DIRECTIVE)
+        {
+            Some(included) if included.starts_with(char::is_whitespace) =>
+            {
+                // Included paths are relative to the including file
+                let included = path.parent()
+                    .unwrap_or(Path::new(""))
+                    .join(included.trim().trim_matches('"'));
+
+                let included = expand(&included, including)?;
+                lines.push(included.strip_suffix('\n').unwrap_or(&included).to_string());
+            },
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    including.pop();
+
+    Ok(lines.join("\n"))
+}
@@ -0,0 +1,53 @@
+use super::*;
+use std::env;
+use std::process;
+
+// A new directory for the files of a test, named after it so tests running at the same time don't share it
+fn directory(test: &str) -> PathBuf
+{
+    let directory = env::temp_dir().join(format!("mini-excel-{}-{}", test, process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    directory
+}
+
+#[test]
+fn include_splices_the_rows_of_another_file() -> ()
+{
+    let directory = directory("include");
+    fs::create_dir_all(directory.join("shared")).unwrap();
+    fs::write(directory.join("shared").join("constants.mini"), "3|4\n").unwrap();
+    fs::write(directory.join("main.mini"), "1|2\n#include shared/constants.mini\n=B0+B1").unwrap();
+
+    let expanded = expand_includes(&directory.join("main.mini"));
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(expanded.unwrap(), "1|2\n3|4\n=B0+B1");
+}
+
+#[test]
+fn circular_include_fails() -> ()
+{
+    let directory = directory("circular-include");
+    fs::write(directory.join("a.mini"), "1\n#include b.mini").unwrap();
+    fs::write(directory.join("b.mini"), "#include a.mini\n2").unwrap();
+
+    let expanded = expand_includes(&directory.join("a.mini"));
+    fs::remove_dir_all(&directory).unwrap();
+
+    let error = expanded.unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Include cycle detected"));
+}
+
+#[test]
+fn missing_include_fails() -> ()
+{
+    let directory = directory("missing-include");
+    fs::write(directory.join("main.mini"), "#include nowhere.mini").unwrap();
+
+    let expanded = expand_includes(&directory.join("main.mini"));
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(expanded.unwrap_err().kind(), io::ErrorKind::NotFound);
+}
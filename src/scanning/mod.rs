@@ -1,5 +1,11 @@
+use std::fmt;
+
+use crate::logging;
 use crate::parsing::CellRef;
 
+#[cfg(test)]
+mod tests;
+
 pub const FUNCTIONS: &[&str] =&
 [
     "random",
@@ -11,23 +17,92 @@ pub const FUNCTIONS: &[&str] =&
     "if",
     "vlookup",
     "concatenate",
+    "row",
+    "column",
+    "offset",
+    "indirect",
+    "maxifs",
+    "minifs",
+    "substitute",
+    "replace",
+    "find",
+    "search",
+    "value",
+    "text",
+    "iseven",
+    "isodd",
+    "sequence",
+    "date",
+    "today",
+    "year",
+    "month",
+    "weekday",
+    "countblank",
+    "round",
+    "roundeven",
+    "sumsq",
+    "averagesq",
+    "percentile",
+    "quartile",
+    "textjoin",
+    "mround",
+    "floorm",
+    "ceilm",
+    "greatest",
+    "least",
+    "clamp",
+    "hypot",
+    "norm",
+    "roundsig",
+    "copysign",
+    "nearbyint",
+    "randarray",
+    "n",
+    "rows",
+    "columns",
+    "first",
+    "last",
+    "weightedaverage",
+    "pi",
+    "degrees",
+    "radians",
+    "asin",
+    "acos",
+    "atan",
+    "atan2",
+    "fact",
+    "combin",
+    "permut",
+    "runningsum",
+    "iserror",
+    "isna",
+    "isref",
+    "joinformat",
+    "coalesce",
+    "sumif",
+    "countif",
 ];
 
 #[derive(Debug, Clone)]
 pub enum LiteralValue
 {
-    Float(f32),
+    Float(f64),
+    // A number written without a decimal point, exact where a `Float` would round (e.g. long IDs)
+    Int(i64),
+    Text(String),
     CellRef(CellIndex),
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum TokenType
 {
-    Number,
-    Plus, Minus, Star, Slash,
+    Number, Text, Boolean,
+    Plus, Minus, Star, Slash, Caret,
+    Bang, And, Or,
+    Equal, NotEqual, Less, LessEqual, Greater, GreaterEqual,
     OpeningParenthese, ClosingParenthese,
     CellRef,
-    Function, Comma
+    Function, Comma, Colon
 }
 
 #[derive(Debug)]
@@ -69,36 +144,80 @@ impl Token
     }
 }
 
+// The sheet comes first, so that cells are ordered sheet by sheet
 #[derive(Debug, Eq, PartialEq, Hash, Clone, PartialOrd, Ord)]
 pub struct CellIndex
 {
+    sheet : usize,
     row   : usize,
     column: usize,
 }
 
 impl CellIndex
 {
+    // A cell of the first sheet, see `with_sheet`
     pub fn new(row: usize, column: usize) -> Self
     {
         CellIndex
         {
+            sheet: 0,
             row,
             column,
         }
     }
 
+    pub fn with_sheet(mut self, sheet: usize) -> Self
+    {
+        self.sheet = sheet;
+        self
+    }
+
     pub fn get(&self) -> (usize, usize)
     {
         (self.row, self.column)
     }
+
+    pub fn sheet(&self) -> usize
+    {
+        self.sheet
+    }
+
+    // A `$` may anchor the letters or the digits (e.g. `$A$1`), it doesn't change the index
+    pub fn from_label(label: &str) -> Option<Self>
+    {
+        let label = label.strip_prefix('$').unwrap_or(label);
+        let letters = label.chars().take_while(Tokenizer::is_alpha).count();
+        let digits = label[letters..].strip_prefix('$').unwrap_or(&label[letters..]);
+
+        if letters == 0 || digits.is_empty() || !digits.chars().all(|c| Tokenizer::is_number(&c))
+        {
+            return None;
+        }
+
+        Some(CellIndex::new(
+            CellRef::text_to_number(label[..letters].to_string()),
+            digits.parse::<usize>().ok()?))
+    }
+}
+
+// Only the label within its sheet, the index doesn't know the sheet's name
+impl fmt::Display for CellIndex
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}{}", CellRef::number_to_text(self.row), self.column)
+    }
 }
 
 pub struct Tokenizer
 {
-    tokens : Vec::<Token>,
-    content: String,
-    start  : usize,
-    current: usize,
+    tokens     : Vec::<Token>,
+    content    : String,
+    start      : usize,
+    current    : usize,
+    origin     : CellIndex,
+    sheet_names: Vec<String>,
+    functions  : Vec<String>,
 }
 
 impl Tokenizer
@@ -111,16 +230,39 @@ impl Tokenizer
             content,
             start: 0,
             current: 0,
+            origin: CellIndex::new(0, 0),
+            sheet_names: Vec::new(),
+            functions: Vec::new(),
         };
     }
 
+    // Tokenizes the formula of `cell`: references get its sheet, or the sheet named by their prefix
+    // (e.g. `Sheet2!A1`), and relative references (e.g. `RC[-1]`) are taken from it. Without any names
+    // prefixed references aren't checked and stay in the sheet of `cell`, without a cell it's `A0`
+    pub fn in_cell(mut self, cell: &CellIndex, sheet_names: &[String]) -> Self
+    {
+        self.origin = cell.clone();
+        self.sheet_names = sheet_names.to_vec();
+        self
+    }
+
+    // Names scanned as functions besides `FUNCTIONS` (e.g. functions registered by an embedder)
+    pub fn with_functions<'n>(mut self, names: impl IntoIterator<Item = &'n String>) -> Self
+    {
+        self.functions = names.into_iter().cloned().collect();
+        self
+    }
+
     pub fn get_tokens(mut self) -> Vec<Token>
     {
         while !self.is_at_end()
         {
             self.scan_token();
         }
-        
+
+        logging::debug!("tokenized `{}` into {:?}", self.content,
+            self.tokens.iter().map(|token| token.get_lexeme().as_str()).collect::<Vec<&str>>());
+
         return self.tokens;
     }
 
@@ -140,8 +282,55 @@ impl Tokenizer
                 '-' => self.add_token(TokenType::Minus, String::from('-')),
                 '*' => self.add_token(TokenType::Star, String::from('*')),
                 '/' => self.add_token(TokenType::Slash, String::from('/')),
+                '^' => self.add_token(TokenType::Caret, String::from('^')),
+
+                '!' if self.peak() == Some('=') =>
+                {
+                    self.get_current_char(); // Consume '='
+                    self.add_token(TokenType::NotEqual, String::from("!="));
+                },
+                '!' => self.add_token(TokenType::Bang, String::from('!')),
+                '&' if self.peak() == Some('&') =>
+                {
+                    self.get_current_char(); // Consume second '&'
+                    self.add_token(TokenType::And, String::from("&&"));
+                },
+                '|' if self.peak() == Some('|') =>
+                {
+                    self.get_current_char(); // Consume second '|'
+                    self.add_token(TokenType::Or, String::from("||"));
+                },
+
+                // `=` and `<>` are the canonical spellings, `==` and `!=` are accepted as aliases
+                '=' if self.peak() == Some('=') =>
+                {
+                    self.get_current_char(); // Consume second '='
+                    self.add_token(TokenType::Equal, String::from("=="));
+                },
+                '=' => self.add_token(TokenType::Equal, String::from('=')),
+                '<' if self.peak() == Some('>') =>
+                {
+                    self.get_current_char(); // Consume '>'
+                    self.add_token(TokenType::NotEqual, String::from("<>"));
+                },
+                '<' if self.peak() == Some('=') =>
+                {
+                    self.get_current_char(); // Consume '='
+                    self.add_token(TokenType::LessEqual, String::from("<="));
+                },
+                '<' => self.add_token(TokenType::Less, String::from('<')),
+                '>' if self.peak() == Some('=') =>
+                {
+                    self.get_current_char(); // Consume '='
+                    self.add_token(TokenType::GreaterEqual, String::from(">="));
+                },
+                '>' => self.add_token(TokenType::Greater, String::from('>')),
 
                 ',' => self.add_token(TokenType::Comma, String::from(',')),
+                ':' => self.add_token(TokenType::Colon, String::from(':')),
+
+                '"' => self.text(),
+                '$' => self.string(),
 
                 _ =>
                 {
@@ -180,58 +369,196 @@ impl Tokenizer
             while !self.is_at_end() && Tokenizer::is_number(&self.peak().unwrap()) { self.current += 1; }
         }
 
+        // Too large integers are floats, like those with a decimal point
         let lexeme = self.content[self.start..self.current].to_string();
-        self.add_token_with_literal(TokenType::Number,
-            lexeme.clone(), LiteralValue::Float(lexeme.parse::<f32>().unwrap()));
+        let literal = match lexeme.parse::<i64>()
+        {
+            Ok(i) => LiteralValue::Int(i),
+            Err(_) => LiteralValue::Float(lexeme.parse::<f64>().unwrap()),
+        };
+        self.add_token_with_literal(TokenType::Number, lexeme, literal);
         
         self.start = self.current;
     }
 
     fn string(&mut self) -> ()
     {
+        let sheet = self.sheet_prefix();
+
+        if let Some(cell_index) = self.relative_reference(sheet.unwrap_or(self.origin.sheet))
+        {
+            let lexeme = self.content[self.start..self.current].to_string();
+            self.add_token_with_literal(TokenType::CellRef, lexeme, LiteralValue::CellRef(cell_index));
+            return;
+        }
+
         while !self.is_at_end() && Tokenizer::is_alpha(&self.peak().unwrap()) { self.current += 1; }
 
-        let ends_with_number = !self.is_at_end() && Tokenizer::is_number(&self.peak().unwrap());
+        if self.peak() == Some('$') { self.current += 1; }
 
-        let numbers_count = self.current - self.start;
+        let ends_with_number = !self.is_at_end() && Tokenizer::is_number(&self.peak().unwrap());
 
         while !self.is_at_end() && Tokenizer::is_number(&self.peak().unwrap()) { self.current += 1; }
 
         let lexeme = self.content[self.start..self.current].to_string();
-        let func = FUNCTIONS.iter().find(|&&s| &s == &lexeme.to_ascii_lowercase().as_str());
-        
-        if !ends_with_number && func.is_none()
+        let name = lexeme.to_ascii_lowercase();
+        let is_function = sheet.is_none() && (FUNCTIONS.contains(&name.as_str()) || self.functions.contains(&name));
+
+        // Booleans are kept numeric, a trailing number makes them a cell reference (e.g. `TRUE1`)
+        if sheet.is_none() && !ends_with_number && ["true", "false"].contains(&lexeme.to_ascii_lowercase().as_str())
+        {
+            let value = if lexeme.eq_ignore_ascii_case("true") { 1.0 } else { 0.0 };
+            self.add_token_with_literal(TokenType::Boolean, lexeme, LiteralValue::Float(value));
+            return;
+        }
+
+        if !ends_with_number && !is_function
         {
             let lexeme = self.content[self.start..self.current].to_string();
             panic!("Invalid token while scanning cell_ref: `{}` at: {}..{}", lexeme, self.start, self.current);
         }
 
-        if func.is_some()
+        if is_function
         {
             self.add_token(TokenType::Function, lexeme);
         }
         else
         {
-            self.add_token_with_literal(TokenType::CellRef,
-                lexeme.clone(),
-                    LiteralValue::CellRef(CellIndex::new(
-                        CellRef::text_to_number(lexeme[..numbers_count].to_string()),
-                        lexeme[numbers_count..].parse::<usize>().unwrap())));
+            let label = lexeme.rsplit('!').next().unwrap();
+            let cell_index = CellIndex::from_label(label).unwrap_or_else(||
+                panic!("Invalid cell reference: `{}` at: {}..{}", lexeme, self.start, self.current))
+                .with_sheet(sheet.unwrap_or(self.origin.sheet));
+            self.add_token_with_literal(TokenType::CellRef, lexeme, LiteralValue::CellRef(cell_index));
         }
 
         self.start = self.current;
     }
 
+    // A name followed by `!` and a reference, otherwise the `!` is left for `!=` or a logical not.
+    // Consumes the name, the `!` and the first character of the reference
+    fn sheet_prefix(&mut self) -> Option<usize>
+    {
+        let name_end = self.current + self.content[self.current..].chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .count();
+
+        let mut after = self.content[name_end..].chars();
+
+        if after.next() != Some('!') || !after.next().is_some_and(|c| Tokenizer::is_alpha(&c) || c == '$')
+        {
+            return None;
+        }
+
+        let name = &self.content[self.start..name_end];
+        let sheet = if self.sheet_names.is_empty()
+        {
+            self.origin.sheet
+        }
+        else
+        {
+            self.sheet_names.iter()
+                .position(|sheet_name| !sheet_name.is_empty() && sheet_name.eq_ignore_ascii_case(name))
+                .unwrap_or_else(|| panic!("Unknown sheet `{}` at: {}..{}", name, self.start, name_end))
+        };
+
+        self.current = name_end + 2;
+
+        Some(sheet)
+    }
+
+    // `R[rows]C[columns]` counts rows and columns from the cell of the formula, a missing offset is 0
+    // (e.g. `RC[-1]` is the cell to the left, `R[-1]C` the one above). Its first character is consumed
+    fn relative_reference(&mut self, sheet: usize) -> Option<CellIndex>
+    {
+        let reference_start = self.current - 1;
+        let rest = &self.content[reference_start..];
+
+        // A `[` can't follow a row or column otherwise
+        let offset = |text: &str| -> Option<(isize, usize)>
+        {
+            let end = text.strip_prefix('[')?.find(']')? + 1;
+            Some((text[1..end].parse::<isize>().ok()?, end + 1))
+        };
+
+        let mut length = 1;
+
+        if !rest.starts_with(['R', 'r'])
+        {
+            return None;
+        }
+
+        let (rows, used) = offset(&rest[length..]).unwrap_or((0, 0));
+        length += used;
+
+        if !rest[length..].starts_with(['C', 'c'])
+        {
+            return None;
+        }
+
+        length += 1;
+
+        let (columns, used) = offset(&rest[length..]).unwrap_or((0, 0));
+        length += used;
+
+        // e.g. `RC5` is the column 5 of the row `RC`
+        if rest[length..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '$' || c == '[')
+        {
+            return None;
+        }
+
+        let lexeme = &rest[..length];
+        let (row, column) = self.origin.get();
+
+        let cell_index = match (row.checked_add_signed(rows), column.checked_add_signed(columns))
+        {
+            (Some(row), Some(column)) => CellIndex::new(row, column).with_sheet(sheet),
+            _ => panic!("Relative reference `{}` from `{}` points before the first row or column", lexeme, self.origin),
+        };
+
+        self.current = reference_start + length;
+
+        Some(cell_index)
+    }
+
+    fn text(&mut self) -> ()
+    {
+        let mut text = String::new();
+
+        loop
+        {
+            if self.is_at_end()
+            {
+                let lexeme = self.content[self.start..self.current].to_string();
+                panic!("Unterminated text: `{}` at: {}..{}", lexeme, self.start, self.current);
+            }
+
+            let c = self.get_current_char();
+
+            if c == '"'
+            {
+                // A doubled quote stands for a literal '"' inside the text
+                if self.peak() != Some('"') { break; }
+
+                self.get_current_char();
+            }
+
+            text.push(c);
+        }
+
+        let lexeme = self.content[self.start..self.current].to_string();
+        self.add_token_with_literal(TokenType::Text, lexeme, LiteralValue::Text(text));
+    }
+
     fn peak(&self) -> Option<char>
     {
-        self.content.chars().nth(self.current)
+        self.content[self.current..].chars().next()
     }
 
     fn get_current_char(&mut self) -> char
     {
-        let c = self.content.chars().nth(self.current);
-        self.current += 1;
-        c.unwrap()
+        let c = self.peak().unwrap();
+        self.current += c.len_utf8();
+        c
     }
 
     fn add_token(&mut self, t: TokenType, lexeme: String) -> ()
@@ -253,11 +580,11 @@ impl Tokenizer
 
     fn is_alpha(c: &char) -> bool
     {
-        (c >= &'a' && c <= &'z') || (c >= &'A' && c <= &'Z')
+        c.is_ascii_alphabetic()
     }
 
     fn is_number(c: &char) -> bool
     {
-        return c >= &'0' && c <= &'9';
+        return c.is_ascii_digit();
     }
 }
@@ -0,0 +1,63 @@
+use super::*;
+
+fn tokens(content: &str) -> Vec<Token>
+{
+    Tokenizer::new(content.to_string()).get_tokens()
+}
+
+fn cell_ref(content: &str) -> CellIndex
+{
+    match tokens(content).remove(0).literal
+    {
+        Some(LiteralValue::CellRef(index)) => index,
+        literal => panic!("Expected a cell reference, got {:?}", literal),
+    }
+}
+
+#[test]
+fn booleans_ignore_case() -> ()
+{
+    for content in ["TRUE", "true", "True", "FALSE", "false"]
+    {
+        assert_eq!(*tokens(content)[0].get_type(), TokenType::Boolean);
+    }
+
+    assert!(matches!(tokens("TRUE")[0].literal, Some(LiteralValue::Float(1.0))));
+    assert!(matches!(tokens("false")[0].literal, Some(LiteralValue::Float(0.0))));
+}
+
+#[test]
+fn boolean_followed_by_digits_is_a_cell_reference() -> ()
+{
+    assert_eq!(*tokens("TRUE1")[0].get_type(), TokenType::CellRef);
+    assert_eq!(cell_ref("TRUE1"), CellIndex::new(CellRef::text_to_number(String::from("TRUE")), 1));
+}
+
+#[test]
+fn references_with_several_letters() -> ()
+{
+    assert_eq!(cell_ref("AA1"), CellIndex::new(26, 1));
+    assert_eq!(cell_ref("AB10"), CellIndex::new(27, 10));
+    assert_eq!(cell_ref("ZZ100"), CellIndex::new(701, 100));
+    assert_eq!(cell_ref("AAA0"), CellIndex::new(702, 0));
+}
+
+#[test]
+fn labels_are_bijective_base_26() -> ()
+{
+    for (row, label) in [(0, "A"), (25, "Z"), (26, "AA"), (27, "AB"), (701, "ZZ"), (702, "AAA")]
+    {
+        assert_eq!(CellRef::number_to_text(row), label);
+        assert_eq!(CellRef::text_to_number(label.to_string()), row);
+    }
+}
+
+#[test]
+fn double_equal_and_bang_equal_are_comparisons() -> ()
+{
+    let types = |content: &str| tokens(content).iter().map(|token| format!("{:?}", token.get_type())).collect::<Vec<_>>();
+
+    assert_eq!(types("A1==1"), types("A1=1"));
+    assert_eq!(types("A1!=1"), types("A1<>1"));
+    assert_eq!(*tokens("!1")[0].get_type(), TokenType::Bang);
+}
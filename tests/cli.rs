@@ -0,0 +1,179 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::io::Write;
+use std::process::{self, Command, Output, Stdio};
+
+// A new directory for the files of a test, named after it so tests running at the same time don't share it
+fn directory(test: &str) -> PathBuf
+{
+    let directory = env::temp_dir().join(format!("mini-excel-cli-{}-{}", test, process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    directory
+}
+
+// Runs the binary in `directory`, so it doesn't pick up a config file or write the default output elsewhere
+fn run(directory: &PathBuf, args: &[&str]) -> Output
+{
+    Command::new(env!("CARGO_BIN_EXE_mini-excel")).current_dir(directory).args(args).output().unwrap()
+}
+
+fn text(bytes: &[u8]) -> String
+{
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[test]
+fn profile_times_the_phases_on_stderr()
+{
+    let directory = directory("profile");
+    fs::write(directory.join("input"), "1|=A0+1").unwrap();
+
+    let plain = run(&directory, &["-o", "-", "input"]);
+    let profiled = run(&directory, &["--profile", "-o", "-", "input"]);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert!(profiled.status.success());
+    assert_eq!(text(&profiled.stdout), text(&plain.stdout));
+
+    let phases: Vec<_> = text(&profiled.stderr).lines().map(|line| line.split(':').next().unwrap().to_string()).collect();
+    assert_eq!(phases, ["read", "load", "tokenize", "parse", "evaluate", "write"]);
+}
+
+#[test]
+fn quiet_prints_nothing()
+{
+    let directory = directory("quiet");
+    fs::write(directory.join("input"), "1|=A0+1").unwrap();
+
+    let saved = run(&directory, &["input"]);
+    let quiet = run(&directory, &["-q", "input"]);
+    let long = run(&directory, &["--quiet", "-o", "copy", "input"]);
+    let output = fs::read_to_string(directory.join("copy")).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(text(&saved.stdout), "Output saved to: output\n");
+    assert_eq!(text(&quiet.stdout), "");
+    assert_eq!(text(&long.stdout), "");
+    assert!(output.starts_with("1 "));
+}
+
+#[test]
+fn stdout_output_has_no_message()
+{
+    let directory = directory("stdout");
+    fs::write(directory.join("input"), "1|=A0+1").unwrap();
+
+    let output = run(&directory, &["--width", "1", "-o", "-", "input"]);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(text(&output.stdout), "1|2|\n");
+}
+
+#[test]
+fn config_precision_is_overridden_by_the_flag()
+{
+    let directory = directory("config");
+    fs::write(directory.join(".mini-excel.toml"), "precision = 2\nwidth = 1\n").unwrap();
+    fs::write(directory.join("input"), "=1/3|=A0*3").unwrap();
+
+    let configured = run(&directory, &["-o", "-", "input"]);
+    let overridden = run(&directory, &["--round-output", "4", "-o", "-", "input"]);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(text(&configured.stdout), "0.33|1|\n");
+    assert_eq!(text(&overridden.stdout), "0.3333|1|\n");
+}
+
+#[test]
+fn no_eval_writes_formulas_as_they_are()
+{
+    let directory = directory("no-eval");
+    fs::write(directory.join("input"), "=random()|=fact(1000000000)|=A0 + 1").unwrap();
+
+    let output = run(&directory, &["--no-eval", "--width", "1", "-o", "-", "input"]);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(text(&output.stdout), "=random()|=fact(1000000000)|=A0 + 1|\n");
+}
+
+#[test]
+fn logging_leaves_the_output_alone()
+{
+    let directory = directory("log");
+    fs::write(directory.join("input"), "1|=A0+1|=sum(A0:A1)").unwrap();
+
+    let plain = run(&directory, &["-o", "-", "input"]);
+    let logged = Command::new(env!("CARGO_BIN_EXE_mini-excel")).current_dir(&directory).env("MINI_EXCEL_LOG", "debug")
+        .args(["-o", "-", "input"]).output().unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(text(&logged.stdout), text(&plain.stdout));
+    assert!(text(&plain.stderr).is_empty());
+    assert!(!text(&logged.stderr).is_empty());
+}
+
+#[test]
+fn output_dir_writes_each_input_to_its_own_file()
+{
+    let directory = directory("output-dir");
+    fs::write(directory.join("first.txt"), "1|=A0+1").unwrap();
+    fs::write(directory.join("second.txt"), "=2*3").unwrap();
+
+    let output = run(&directory, &["-q", "--width", "1", "--output-dir", "out", "--output-name", "{name}.csv", "first.txt", "second.txt"]);
+    let first = fs::read_to_string(directory.join("out").join("first.csv")).unwrap();
+    let second = fs::read_to_string(directory.join("out").join("second.csv")).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(first, "1|2|\n");
+    assert_eq!(second, "6|\n");
+}
+
+#[test]
+fn several_inputs_need_an_output_dir()
+{
+    let directory = directory("inputs");
+    fs::write(directory.join("first.txt"), "1").unwrap();
+    fs::write(directory.join("second.txt"), "2").unwrap();
+
+    let output = run(&directory, &["-q", "first.txt", "second.txt"]);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert!(!output.status.success());
+}
+
+#[test]
+fn repl_evaluates_each_line()
+{
+    let mut repl = Command::new(env!("CARGO_BIN_EXE_mini-excel")).arg("--repl")
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+
+    // No newline after the last line, the end of the input still ends it
+    repl.stdin.take().unwrap().write_all(b"1 + 2\nset A1 = 5\nset A2 = A1 * 2\nsum(A0:A5)\n\nB7\n2^10").unwrap();
+    let output = repl.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(text(&output.stdout), "3\n5\n10\n15\n1024\n");
+    assert_eq!(text(&output.stderr), "error: Referring to an unknown cell B7\n");
+}
+
+#[test]
+fn diff_prints_the_differing_cells()
+{
+    let directory = directory("diff");
+    fs::write(directory.join("old"), "1|2|=A0+A1\nx|y").unwrap();
+    fs::write(directory.join("new"), "1|2.5|=A0+A1\nx|y").unwrap();
+    fs::write(directory.join("copy"), "1|2|3\nx|y").unwrap();
+
+    let differing = run(&directory, &["--diff", "old", "new"]);
+    let same = run(&directory, &["--diff", "old", "copy"]);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(differing.status.code(), Some(1));
+    assert_eq!(text(&differing.stdout), "~ A1: 2 -> 2.5 (+0.5)\n~ A2: 3 -> 3.5 (+0.5)\n");
+    assert_eq!(same.status.code(), Some(0));
+    assert_eq!(text(&same.stdout), "");
+}